@@ -0,0 +1,93 @@
+use std::{cmp, error, fmt, thread};
+
+use super::{eval, tokenize, EvalError, LexError};
+
+/// Why a line of source text could not be evaluated.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LineError {
+    Lex(LexError),
+    Eval(EvalError),
+}
+
+impl From<LexError> for LineError {
+    fn from(e: LexError) -> Self {
+        LineError::Lex(e)
+    }
+}
+
+impl From<EvalError> for LineError {
+    fn from(e: EvalError) -> Self {
+        LineError::Eval(e)
+    }
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LineError::Lex(ref e) => write!(f, "{}", e),
+            LineError::Eval(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for LineError {}
+
+/// Tokenizes and evaluates a single line of source text.
+pub fn eval_line(line: &str) -> Result<isize, LineError> {
+    Ok(eval(&tokenize(line)?)?)
+}
+
+/// Evaluates every line as an independent expression, spread over up to
+/// `threads` worker threads.
+///
+/// The results are in the order of `lines`; a line that fails does not stop
+/// the others. With `threads` of 0 or 1 everything runs on the calling
+/// thread.
+pub fn eval_batch<S: AsRef<str> + Sync>(
+    lines: &[S],
+    threads: usize,
+) -> Vec<Result<isize, LineError>> {
+    let eval_all =
+        |chunk: &[S]| -> Vec<_> { chunk.iter().map(|l| eval_line(l.as_ref())).collect() };
+    if threads <= 1 || lines.len() <= 1 {
+        return eval_all(lines);
+    }
+
+    // One contiguous chunk per thread keeps the results in order when the
+    // chunks are joined back together.
+    let chunk_size = lines.len().div_ceil(cmp::min(threads, lines.len()));
+    thread::scope(|scope| {
+        let workers: Vec<_> = lines
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || eval_all(chunk)))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::LexErrorKind;
+    use super::*;
+
+    #[test]
+    fn test_eval_batch() {
+        let lines = ["1 2 +", "1 0 /", "2 $", "", "6 7 *"];
+        let expected = vec![
+            Ok(3),
+            Err(LineError::Eval(EvalError::DivisionByZero { index: 2 })),
+            Err(LineError::Lex(LexError {
+                kind: LexErrorKind::UnknownSymbol,
+                span: 2..3,
+            })),
+            Err(LineError::Eval(EvalError::Empty)),
+            Ok(42),
+        ];
+        for &threads in &[0, 1, 2, 3, 16] {
+            assert_eq!(eval_batch(&lines, threads), expected);
+        }
+    }
+}
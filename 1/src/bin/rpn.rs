@@ -0,0 +1,194 @@
+//! Reverse-Polish calculator.
+//!
+//! `rpn` starts an interactive session: every line is pushed onto a stack
+//! that persists between lines, and the stack is shown after each line.
+//! Besides expressions it understands `clear`, `undo`, `history` and `quit`.
+//!
+//! `rpn --stdin` evaluates every line of standard input as a separate
+//! expression and writes one result or error per line to standard output.
+//! `rpn --batch FILE` does the same for the lines of `FILE`, evaluating them
+//! on all available cores.
+
+extern crate your;
+
+use std::io::{self, BufRead, Write};
+use std::{env, fs, process, thread};
+
+use your::{eval_batch, eval_line, tokenize, Evaluator, LineError};
+
+const USAGE: &str = "usage: rpn [--stdin | --batch FILE]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => interactive(stdin.lock(), stdout.lock()),
+        ["--stdin"] => each_line(stdin.lock(), stdout.lock()),
+        ["--batch", path] => batch(path, stdout.lock()),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("rpn: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Evaluates every line as an independent expression.
+fn each_line<R: BufRead, W: Write>(input: R, mut output: W) -> io::Result<()> {
+    for line in input.lines() {
+        write_result(&mut output, eval_line(&line?))?;
+    }
+    Ok(())
+}
+
+/// Evaluates every line of the file at `path` on as many threads as there
+/// are cores, writing the results in the order of the lines.
+fn batch<W: Write>(path: &str, mut output: W) -> io::Result<()> {
+    let source = fs::read_to_string(path)?;
+    let lines: Vec<&str> = source.lines().collect();
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    for result in eval_batch(&lines, threads) {
+        write_result(&mut output, result)?;
+    }
+    Ok(())
+}
+
+fn write_result<W: Write>(output: &mut W, result: Result<isize, LineError>) -> io::Result<()> {
+    match result {
+        Ok(value) => writeln!(output, "{}", value),
+        Err(e) => writeln!(output, "error: {}", e),
+    }
+}
+
+/// Runs the interactive calculator until `quit` or the end of input.
+fn interactive<R: BufRead, W: Write>(mut input: R, mut output: W) -> io::Result<()> {
+    let mut evaluator: Evaluator = Evaluator::new();
+    // The state before every line in `history`, for `undo`.
+    let mut snapshots: Vec<Evaluator> = Vec::new();
+    let mut history: Vec<String> = Vec::new();
+    let mut line = String::new();
+
+    loop {
+        write!(output, "> ")?;
+        output.flush()?;
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            writeln!(output)?;
+            return Ok(());
+        }
+
+        match line.trim() {
+            "" => continue,
+            "quit" | "exit" => return Ok(()),
+            "history" => {
+                for (i, entry) in history.iter().enumerate() {
+                    writeln!(output, "{:>4}  {}", i + 1, entry)?;
+                }
+                continue;
+            }
+            "undo" => match snapshots.pop() {
+                Some(previous) => {
+                    evaluator = previous;
+                    history.pop();
+                }
+                None => writeln!(output, "nothing to undo")?,
+            },
+            "clear" => {
+                snapshots.push(evaluator.clone());
+                history.push("clear".to_string());
+                evaluator.clear();
+            }
+            text => match push_line(&evaluator, text) {
+                Ok(next) => {
+                    snapshots.push(evaluator);
+                    history.push(text.to_string());
+                    evaluator = next;
+                }
+                Err(message) => writeln!(output, "error: {}", message)?,
+            },
+        }
+        show_stack(&mut output, &evaluator)?;
+    }
+}
+
+/// Pushes every token of `text` onto a copy of `evaluator`. The line is
+/// all-or-nothing: on error the original stack is kept. Errors count tokens
+/// from the start of the line, not of the session.
+fn push_line(evaluator: &Evaluator, text: &str) -> Result<Evaluator, String> {
+    let tokens = tokenize(text).map_err(|e| format!("`{}`: {}", &text[e.span.clone()], e))?;
+    let mut next = evaluator.clone();
+    for (index, token) in tokens.into_iter().enumerate() {
+        let word = token.to_string();
+        next.push(token)
+            .map_err(|e| format!("`{}`: {}", word, e.with_index(index)))?;
+    }
+    Ok(next)
+}
+
+fn show_stack<W: Write>(output: &mut W, evaluator: &Evaluator) -> io::Result<()> {
+    if evaluator.depth() == 0 {
+        return writeln!(output, "(empty)");
+    }
+    let values: Vec<String> = evaluator.stack().iter().map(|v| v.to_string()).collect();
+    writeln!(output, "{}", values.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn session(input: &str) -> String {
+        let mut output = Vec::new();
+        interactive(Cursor::new(input), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_interactive() {
+        assert_eq!(session("1 2\n+ 3 *\n"), "> 1 2\n> 9\n> \n");
+        assert_eq!(
+            session("1 2\nclear\n5\nundo\nundo\nhistory\nquit\n"),
+            "> 1 2\n> (empty)\n> 5\n> (empty)\n> 1 2\n>    1  1 2\n> "
+        );
+        assert_eq!(session("undo\n"), "> nothing to undo\n(empty)\n> \n");
+        // A failing line leaves the stack as it was.
+        assert_eq!(
+            session("4\n2 +  0 /\n"),
+            "> 4\n> error: `/`: token 3: division by zero\n4\n> \n"
+        );
+    }
+
+    #[test]
+    fn test_interactive_error_index() {
+        // Indices restart on every line, whatever came before.
+        assert_eq!(
+            session(
+                "1 2 3
++ + 5
+0 /
+"
+            ),
+            "> 1 2 3
+> 6 5
+> error: `/`: token 1: division by zero
+6 5
+> 
+"
+        );
+    }
+
+    #[test]
+    fn test_each_line() {
+        let mut output = Vec::new();
+        each_line(Cursor::new("1 2 +\n1 0 /\n2 $\n"), &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "3\nerror: token 2: division by zero\nerror: unknown symbol at 2..3\n"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use super::expr::fold_postfix;
+use super::number::divides_exactly;
+use super::{EvalError, EvalOptions, Expr, Number, Operator, Token};
+
+/// Returns the derivative of a postfix expression with respect to the
+/// variable `var`, simplified like `optimize`.
+///
+/// Sums, differences, products, quotients, negation and powers with an
+/// exponent that does not depend on `var` are differentiated; any operator
+/// applied to something that depends on `var` is `NotDifferentiable`. So is
+/// `/` for types whose division truncates, like `isize`, since the quotient
+/// rule needs exact division; differentiate over `Rational` or `f64`. Terms
+/// that are zero by construction are left out, so the derivative may be
+/// defined where the expression is not: `x 1 0 / +` has the derivative `1`.
+pub fn derive<N: Number + PartialEq>(
+    tokens: &[Token<N>],
+    var: &str,
+) -> Result<Vec<Token<N>>, EvalError> {
+    let exact_division = divides_exactly::<N>();
+
+    // Every value is a subexpression and its derivative.
+    let (_, derivative) = fold_postfix(
+        tokens,
+        |value| (Expr::Number(value.clone()), zero()),
+        |name| {
+            let derivative = if name == var {
+                Expr::Number(N::one())
+            } else {
+                zero()
+            };
+            (Expr::Variable(name.to_string()), derivative)
+        },
+        |index, op, operands| {
+            let (args, derivatives): (Vec<_>, Vec<_>) = operands.into_iter().unzip();
+            let derivative = differentiate(op, &args, &derivatives, exact_division)
+                .ok_or(EvalError::NotDifferentiable { index })?;
+            Ok((Expr::Apply(op, args), derivative))
+        },
+    )?;
+    Ok(derivative.simplify(&EvalOptions::default()).to_postfix())
+}
+
+/// Returns the derivative of `op` applied to `args`, given the derivatives of
+/// `args`, or `None` if there is no rule for it.
+fn differentiate<N: Number + PartialEq>(
+    op: Operator,
+    args: &[Expr<N>],
+    derivatives: &[Expr<N>],
+    exact_division: bool,
+) -> Option<Expr<N>> {
+    if derivatives.iter().all(is_zero) {
+        return Some(zero());
+    }
+    let (u, du) = (&args[0], &derivatives[0]);
+    let (v, dv) = (args.last().unwrap(), derivatives.last().unwrap());
+    let derivative = match op {
+        Operator::Add | Operator::Sub => apply(op, du.clone(), dv.clone()),
+        Operator::Mul => apply(
+            Operator::Add,
+            apply(op, du.clone(), v.clone()),
+            apply(op, u.clone(), dv.clone()),
+        ),
+        Operator::Div if exact_division => apply(
+            op,
+            apply(
+                Operator::Sub,
+                apply(Operator::Mul, du.clone(), v.clone()),
+                apply(Operator::Mul, u.clone(), dv.clone()),
+            ),
+            apply(Operator::Mul, v.clone(), v.clone()),
+        ),
+        Operator::Neg => neg(du.clone()),
+        Operator::Pow if is_zero(dv) => {
+            let exponent = apply(Operator::Sub, v.clone(), Expr::Number(N::one()));
+            let outer = apply(Operator::Mul, v.clone(), apply(op, u.clone(), exponent));
+            apply(Operator::Mul, outer, du.clone())
+        }
+        _ => return None,
+    };
+    Some(derivative)
+}
+
+/// Applies a binary operator, leaving out terms that are zero.
+fn apply<N: Number + PartialEq>(op: Operator, x: Expr<N>, y: Expr<N>) -> Expr<N> {
+    match op {
+        Operator::Add | Operator::Sub if is_zero(&y) => x,
+        Operator::Add if is_zero(&x) => y,
+        Operator::Sub if is_zero(&x) => neg(y),
+        Operator::Mul if is_zero(&x) || is_zero(&y) => zero(),
+        _ => Expr::Apply(op, vec![x, y]),
+    }
+}
+
+fn neg<N: Number + PartialEq>(x: Expr<N>) -> Expr<N> {
+    if is_zero(&x) {
+        x
+    } else {
+        Expr::Apply(Operator::Neg, vec![x])
+    }
+}
+
+fn zero<N: Number>() -> Expr<N> {
+    Expr::Number(N::zero())
+}
+
+fn is_zero<N: Number + PartialEq>(expr: &Expr<N>) -> bool {
+    match *expr {
+        Expr::Number(ref value) => *value == N::zero(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{eval_with, tokenize, Rational};
+    use super::*;
+
+    fn derived(source: &str) -> Vec<Token> {
+        derive(&tokenize(source).unwrap(), "x").unwrap()
+    }
+
+    fn exact(source: &str) -> Vec<Token<Rational>> {
+        let tokens = tokenize(source).unwrap();
+        tokens.iter().map(|t| t.map(|&n| n.into())).collect()
+    }
+
+    #[test]
+    fn test_derive() {
+        assert_eq!(derived("x x * 3 x * +"), tokenize("x x + 3 +").unwrap());
+        assert_eq!(derived("x 3 **"), tokenize("3 x 2 ** *").unwrap());
+        assert_eq!(derived("y abs x * 5 -"), tokenize("y abs").unwrap());
+        assert_eq!(derived("y 2 **"), tokenize("0").unwrap());
+
+        // d/dx (x + 1)(x - y) = 2x + 1 - y
+        let derivative = derived("x 1 + x y - *");
+        let env = [("x", 4), ("y", 2)];
+        assert_eq!(eval_with(&derivative, &env[..]), Ok(7));
+
+        let derive_exact = |source| derive(&exact(source), "x").unwrap();
+        assert_eq!(derive_exact("1 x /"), exact("-1 x x * /"));
+        assert_eq!(
+            derive_exact("x 2 /"),
+            vec![Token::Operand(Rational::new(1, 2).unwrap())]
+        );
+        let derivative = derive_exact("x x * 3 /");
+        let env = [("x", Rational::from_integer(1))];
+        assert_eq!(
+            eval_with(&derivative, &env[..]),
+            Ok(Rational::new(2, 3).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_derive_errors() {
+        assert_eq!(
+            derive(&tokenize("x abs").unwrap(), "x"),
+            Err(EvalError::NotDifferentiable { index: 1 })
+        );
+        assert_eq!(
+            derive(&tokenize("2 x **").unwrap(), "x"),
+            Err(EvalError::NotDifferentiable { index: 2 })
+        );
+        // Integer division truncates, so the quotient rule does not hold.
+        assert_eq!(
+            derive(&tokenize("x 2 /").unwrap(), "x"),
+            Err(EvalError::NotDifferentiable { index: 2 })
+        );
+        assert_eq!(
+            derive(&tokenize("x +").unwrap(), "x"),
+            Err(EvalError::StackUnderflow { index: 1, depth: 1 })
+        );
+    }
+}
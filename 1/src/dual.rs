@@ -0,0 +1,267 @@
+use std::cmp::{self, Ordering};
+
+use super::number::divides_exactly;
+use super::{eval_with, ArithError, Environment, EvalError, EvalOptions, Number, Token};
+
+/// A value together with its partial derivatives with respect to a list of
+/// variables, for forward-mode automatic differentiation.
+///
+/// Partial derivatives past the end of `partials()` are zero, so constants
+/// carry no derivatives at all.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dual<N = f64> {
+    value: N,
+    partials: Vec<N>,
+}
+
+impl<N: Number> Dual<N> {
+    /// Creates a value that depends on no variable.
+    pub fn constant(value: N) -> Self {
+        Dual {
+            value,
+            partials: Vec::new(),
+        }
+    }
+
+    /// Creates the value of variable number `index`, whose derivative with
+    /// respect to itself is 1.
+    pub fn variable(value: N, index: usize) -> Self {
+        let mut partials = vec![N::zero(); index];
+        partials.push(N::one());
+        Dual { value, partials }
+    }
+
+    /// Returns the value without its derivatives.
+    pub fn value(&self) -> &N {
+        &self.value
+    }
+
+    /// Returns the partial derivatives, without trailing zeros that were
+    /// never computed.
+    pub fn partials(&self) -> &[N] {
+        &self.partials
+    }
+
+    /// Returns the partial derivative with respect to variable `index`.
+    pub fn partial(&self, index: usize) -> N {
+        self.partials.get(index).cloned().unwrap_or_else(N::zero)
+    }
+
+    fn is_constant(&self) -> Result<bool, ArithError> {
+        for d in &self.partials {
+            if d.compare(&N::zero())? != Ordering::Equal {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Combines the partial derivatives of `self` and `rhs` pairwise.
+    fn zip<F>(&self, rhs: &Self, f: F) -> Result<Vec<N>, ArithError>
+    where
+        F: Fn(&N, &N) -> Result<N, ArithError>,
+    {
+        let len = cmp::max(self.partials.len(), rhs.partials.len());
+        (0..len)
+            .map(|i| f(&self.partial(i), &rhs.partial(i)))
+            .collect()
+    }
+
+    fn map<F>(&self, value: N, f: F) -> Result<Self, ArithError>
+    where
+        F: Fn(&N) -> Result<N, ArithError>,
+    {
+        let partials = self.partials.iter().map(f).collect::<Result<_, _>>()?;
+        Ok(Dual { value, partials })
+    }
+}
+
+/// Derivatives follow the usual rules of calculus. `rem` and the bitwise
+/// operators are unsupported, as is `**` with an exponent that depends on a
+/// variable, and `/` if division truncates for `N`, as for integers.
+impl<N: Number> Number for Dual<N> {
+    fn zero() -> Self {
+        Dual::constant(N::zero())
+    }
+
+    fn one() -> Self {
+        Dual::constant(N::one())
+    }
+
+    fn add(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+        Ok(Dual {
+            value: self.value.add(&rhs.value, options)?,
+            partials: self.zip(rhs, |a, b| a.add(b, options))?,
+        })
+    }
+
+    fn sub(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+        Ok(Dual {
+            value: self.value.sub(&rhs.value, options)?,
+            partials: self.zip(rhs, |a, b| a.sub(b, options))?,
+        })
+    }
+
+    fn mul(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+        let (u, v) = (&self.value, &rhs.value);
+        Ok(Dual {
+            value: u.mul(v, options)?,
+            partials: self.zip(rhs, |du, dv| {
+                du.mul(v, options)?.add(&u.mul(dv, options)?, options)
+            })?,
+        })
+    }
+
+    fn div(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+        if !divides_exactly::<N>() {
+            return Err(ArithError::Unsupported);
+        }
+        // (u / v)' = (u' - (u / v) v') / v
+        let quotient = self.value.div(&rhs.value, options)?;
+        let partials = self.zip(rhs, |du, dv| {
+            du.sub(&quotient.mul(dv, options)?, options)?
+                .div(&rhs.value, options)
+        })?;
+        Ok(Dual {
+            value: quotient,
+            partials,
+        })
+    }
+
+    fn pow(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+        if !rhs.is_constant()? {
+            return Err(ArithError::Unsupported);
+        }
+        let (u, n) = (&self.value, &rhs.value);
+        let value = u.pow(n, options)?;
+        if n.compare(&N::zero())? == Ordering::Equal {
+            return Ok(Dual::constant(value));
+        }
+        // (u ** n)' = n u ** (n - 1) u'
+        let factor = n.mul(&u.pow(&n.sub(&N::one(), options)?, options)?, options)?;
+        self.map(value, |du| factor.mul(du, options))
+    }
+
+    fn neg(&self, options: &EvalOptions) -> Result<Self, ArithError> {
+        self.map(self.value.neg(options)?, |du| du.neg(options))
+    }
+
+    /// Takes the derivative from the right at 0.
+    fn abs(&self, options: &EvalOptions) -> Result<Self, ArithError> {
+        if self.value.compare(&N::zero())? == Ordering::Less {
+            self.neg(options)
+        } else {
+            Ok(self.clone())
+        }
+    }
+
+    fn min(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        match self.compare(rhs)? {
+            Ordering::Greater => Ok(rhs.clone()),
+            _ => Ok(self.clone()),
+        }
+    }
+
+    fn max(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        match self.compare(rhs)? {
+            Ordering::Less => Ok(rhs.clone()),
+            _ => Ok(self.clone()),
+        }
+    }
+
+    /// Compares the values only.
+    fn compare(&self, rhs: &Self) -> Result<Ordering, ArithError> {
+        self.value.compare(&rhs.value)
+    }
+
+    fn signed_zero() -> bool {
+        N::signed_zero()
+    }
+}
+
+/// Looks up variables in an `Environment<N>`, seeding the ones to
+/// differentiate by.
+struct Seeded<'a, E: ?Sized + 'a> {
+    env: &'a E,
+    vars: &'a [&'a str],
+}
+
+impl<'a, N: Number, E: Environment<N> + ?Sized> Environment<Dual<N>> for Seeded<'a, E> {
+    fn get(&self, name: &str) -> Option<Dual<N>> {
+        let value = self.env.get(name)?;
+        Some(match self.vars.iter().position(|&var| var == name) {
+            Some(index) => Dual::variable(value, index),
+            None => Dual::constant(value),
+        })
+    }
+}
+
+/// Evaluates `tokens` with variables from `env` and returns the value and
+/// its partial derivatives with respect to each of `vars`, in one pass.
+///
+/// Unlike `derive`, the cost does not depend on how large the derivative
+/// expression would be. An operator without a derivative rule fails with
+/// `EvalError::Unsupported`.
+pub fn eval_gradient<N: Number, E: Environment<N> + ?Sized>(
+    tokens: &[Token<N>],
+    env: &E,
+    vars: &[&str],
+) -> Result<(N, Vec<N>), EvalError> {
+    let tokens: Vec<Token<Dual<N>>> = tokens
+        .iter()
+        .map(|t| t.map(|n| Dual::constant(n.clone())))
+        .collect();
+    let result = eval_with(&tokens, &Seeded { env, vars })?;
+    let partials = (0..vars.len()).map(|i| result.partial(i)).collect();
+    Ok((result.value, partials))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{tokenize, Operator};
+    use super::*;
+
+    #[test]
+    fn test_eval_gradient() {
+        let tokens = tokenize("x y * x + 2 -").unwrap();
+        let env = [("x", 3), ("y", 4)];
+        assert_eq!(
+            eval_gradient(&tokens, &env[..], &["x", "y"]),
+            Ok((13, vec![5, 3]))
+        );
+        assert_eq!(eval_gradient(&tokens, &env[..], &["y"]), Ok((13, vec![3])));
+
+        // x ** 2 / y
+        let tokens = [
+            Token::Variable("x".to_string()),
+            Token::Operand(2.0),
+            Token::Operator(Operator::Pow),
+            Token::Variable("y".to_string()),
+            Token::Operator(Operator::Div),
+        ];
+        let env = [("x", 3.0), ("y", 2.0)];
+        assert_eq!(
+            eval_gradient(&tokens, &env[..], &["x", "y"]),
+            Ok((4.5, vec![3.0, -2.25]))
+        );
+    }
+
+    #[test]
+    fn test_eval_gradient_errors() {
+        let tokens = tokenize("2 x **").unwrap();
+        assert_eq!(
+            eval_gradient(&tokens, &[("x", 3)][..], &["x"]),
+            Err(EvalError::Unsupported { index: 2 })
+        );
+        assert_eq!(
+            eval_gradient(&tokens, &[("x", 3)][..], &[]),
+            Ok((8, vec![]))
+        );
+        // Integer division truncates, so the quotient rule does not hold.
+        let tokens = tokenize("x y /").unwrap();
+        assert_eq!(
+            eval_gradient(&tokens, &[("x", 7), ("y", 2)][..], &["x", "y"]),
+            Err(EvalError::Unsupported { index: 2 })
+        );
+    }
+}
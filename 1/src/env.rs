@@ -0,0 +1,51 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+
+/// Resolves the names of `Token::Variable`s to values during evaluation.
+pub trait Environment<N> {
+    /// Returns the value bound to `name`, or `None` if it is unbound.
+    fn get(&self, name: &str) -> Option<N>;
+}
+
+impl<N: Clone, S: BuildHasher> Environment<N> for HashMap<String, N, S> {
+    fn get(&self, name: &str) -> Option<N> {
+        HashMap::get(self, name).cloned()
+    }
+}
+
+impl<N: Clone, S: BuildHasher> Environment<N> for HashMap<&str, N, S> {
+    fn get(&self, name: &str) -> Option<N> {
+        HashMap::get(self, name).cloned()
+    }
+}
+
+impl<N: Clone> Environment<N> for BTreeMap<String, N> {
+    fn get(&self, name: &str) -> Option<N> {
+        BTreeMap::get(self, name).cloned()
+    }
+}
+
+impl<N: Clone> Environment<N> for BTreeMap<&str, N> {
+    fn get(&self, name: &str) -> Option<N> {
+        BTreeMap::get(self, name).cloned()
+    }
+}
+
+/// A list of `(name, value)` pairs; the first matching name wins.
+impl<N: Clone> Environment<N> for [(&str, N)] {
+    fn get(&self, name: &str) -> Option<N> {
+        self.iter()
+            .find(|pair| pair.0 == name)
+            .map(|pair| pair.1.clone())
+    }
+}
+
+/// The environment used by `eval`, in which every variable is unbound.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoVariables;
+
+impl<N> Environment<N> for NoVariables {
+    fn get(&self, _name: &str) -> Option<N> {
+        None
+    }
+}
@@ -0,0 +1,126 @@
+use super::machine::Machine;
+use super::{Environment, EvalError, EvalOptions, NoVariables, Number, Token};
+
+/// Evaluates a postfix expression that arrives one token at a time.
+///
+/// Each `push` costs only the work for that token, however long the
+/// expression grows. A push that fails leaves the stack as it was, so the
+/// caller may carry on with other tokens; it still counts towards the token
+/// indices reported in errors.
+#[derive(Clone, Debug)]
+pub struct Evaluator<N = isize> {
+    machine: Machine<N>,
+    options: EvalOptions,
+    next_index: usize,
+}
+
+impl<N: Number> Evaluator<N> {
+    /// Creates an evaluator with an empty stack and the default options.
+    pub fn new() -> Self {
+        Evaluator::with_options(EvalOptions::default())
+    }
+
+    /// Creates an evaluator with an empty stack that computes with `options`.
+    pub fn with_options(options: EvalOptions) -> Self {
+        Evaluator {
+            machine: Machine::new(),
+            options,
+            next_index: 0,
+        }
+    }
+
+    /// Evaluates the next token.
+    pub fn push(&mut self, token: Token<N>) -> Result<(), EvalError> {
+        self.push_with(token, &NoVariables)
+    }
+
+    /// Evaluates the next token, looking up a variable in `env`.
+    pub fn push_with<E: Environment<N> + ?Sized>(
+        &mut self,
+        token: Token<N>,
+        env: &E,
+    ) -> Result<(), EvalError> {
+        let index = self.next_index;
+        self.next_index += 1;
+        self.machine.step(&token, index, env, &self.options)
+    }
+
+    /// Returns the value on top of the stack.
+    pub fn peek(&self) -> Option<&N> {
+        self.machine.values().last()
+    }
+
+    /// Returns the number of values on the stack.
+    pub fn depth(&self) -> usize {
+        self.machine.values().len()
+    }
+
+    /// Returns the values on the stack, bottom first.
+    pub fn stack(&self) -> &[N] {
+        self.machine.values()
+    }
+
+    /// Returns the number of tokens pushed so far.
+    pub fn len(&self) -> usize {
+        self.next_index
+    }
+
+    /// Returns `true` if no token has been pushed yet.
+    pub fn is_empty(&self) -> bool {
+        self.next_index == 0
+    }
+
+    /// Empties the stack and restarts token indices from 0.
+    pub fn clear(&mut self) {
+        self.machine = Machine::new();
+        self.next_index = 0;
+    }
+
+    /// Returns the value of the expression, which must have left exactly one
+    /// value on the stack.
+    pub fn finish(self) -> Result<N, EvalError> {
+        self.machine.finish()
+    }
+}
+
+impl<N: Number> Default for Evaluator<N> {
+    fn default() -> Self {
+        Evaluator::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{tokenize, Operator};
+    use super::*;
+
+    #[test]
+    fn test_evaluator() {
+        let mut evaluator = Evaluator::new();
+        for token in tokenize("3 4 +").unwrap() {
+            evaluator.push(token).unwrap();
+        }
+        assert_eq!(evaluator.peek(), Some(&7));
+        assert_eq!(evaluator.depth(), 1);
+
+        assert_eq!(
+            evaluator.push(Token::Operator(Operator::Mul)),
+            Err(EvalError::StackUnderflow { index: 3, depth: 1 })
+        );
+        assert_eq!(evaluator.stack(), &[7]);
+
+        evaluator.push(Token::Operand(0)).unwrap();
+        assert_eq!(
+            evaluator.push(Token::Operator(Operator::Div)),
+            Err(EvalError::DivisionByZero { index: 5 })
+        );
+        assert_eq!(evaluator.stack(), &[7, 0]);
+        assert_eq!(
+            evaluator.clone().finish(),
+            Err(EvalError::LeftoverOperands { index: 4, depth: 2 })
+        );
+
+        evaluator.push(Token::Operator(Operator::Add)).unwrap();
+        assert_eq!(evaluator.finish(), Ok(7));
+    }
+}
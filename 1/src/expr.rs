@@ -0,0 +1,182 @@
+use std::{cmp, fmt};
+
+use super::{analyze, to_infix, EvalError, Operator, Token};
+
+/// A postfix expression as a tree.
+///
+/// Stack words have no node of their own: `x dup *` becomes
+/// `Apply(Mul, [x, x])`, so a duplicated subexpression appears twice. A tree
+/// built by hand may apply an operator to the wrong number of arguments; such
+/// a node is displayed in postfix form and left alone by `simplify`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr<N = isize> {
+    Number(N),
+    Variable(String),
+    /// An operator applied to `op.arity()` arguments, in push order.
+    Apply(Operator, Vec<Expr<N>>),
+}
+
+/// Callbacks for `Expr::walk`. Every method does nothing by default.
+pub trait Visitor<N> {
+    fn visit_number(&mut self, _value: &N) {}
+    fn visit_variable(&mut self, _name: &str) {}
+    /// Called after all of `args` have been visited.
+    fn visit_apply(&mut self, _op: Operator, _args: &[Expr<N>]) {}
+}
+
+impl<N: Clone> Expr<N> {
+    /// Builds the tree of a postfix expression, which must be valid.
+    pub fn from_postfix(tokens: &[Token<N>]) -> Result<Expr<N>, EvalError> {
+        fold_postfix(
+            tokens,
+            |value| Expr::Number(value.clone()),
+            |name| Expr::Variable(name.to_string()),
+            |_, op, args| Ok(Expr::Apply(op, args)),
+        )
+    }
+
+    /// Returns the postfix tokens of the tree. No stack words are used.
+    pub fn to_postfix(&self) -> Vec<Token<N>> {
+        let mut tokens = Vec::new();
+        self.push_postfix(&mut tokens);
+        tokens
+    }
+
+    fn push_postfix(&self, tokens: &mut Vec<Token<N>>) {
+        match *self {
+            Expr::Number(ref value) => tokens.push(Token::Operand(value.clone())),
+            Expr::Variable(ref name) => tokens.push(Token::Variable(name.clone())),
+            Expr::Apply(op, ref args) => {
+                for arg in args {
+                    arg.push_postfix(tokens);
+                }
+                tokens.push(Token::Operator(op));
+            }
+        }
+    }
+}
+
+/// Computes a value for a valid postfix expression bottom-up, the way
+/// evaluation would: `number` and `variable` make the value of a leaf, and
+/// `apply` that of the operator at some index from the values of its
+/// arguments, in push order. Stack words rearrange the values.
+pub(crate) fn fold_postfix<N, T, F, G, H>(
+    tokens: &[Token<N>],
+    mut number: F,
+    mut variable: G,
+    mut apply: H,
+) -> Result<T, EvalError>
+where
+    T: Clone,
+    F: FnMut(&N) -> T,
+    G: FnMut(&str) -> T,
+    H: FnMut(usize, Operator, Vec<T>) -> Result<T, EvalError>,
+{
+    analyze(tokens)?;
+
+    let mut stack: Vec<T> = Vec::new();
+    for (index, token) in tokens.iter().enumerate() {
+        match *token {
+            Token::Operand(ref value) => stack.push(number(value)),
+            Token::Variable(ref name) => stack.push(variable(name)),
+            Token::Stack(op) => op.apply(&mut stack),
+            Token::Operator(op) => {
+                let at = stack.len() - op.arity();
+                let args = stack.split_off(at);
+                stack.push(apply(index, op, args)?);
+            }
+            // `analyze` rejects definitions.
+            Token::Define | Token::End => unreachable!(),
+        }
+    }
+    Ok(stack.pop().unwrap())
+}
+
+impl<N> Expr<N> {
+    /// Visits every node in postfix order: children before their parent.
+    pub fn walk<V: Visitor<N> + ?Sized>(&self, visitor: &mut V) {
+        match *self {
+            Expr::Number(ref value) => visitor.visit_number(value),
+            Expr::Variable(ref name) => visitor.visit_variable(name),
+            Expr::Apply(op, ref args) => {
+                for arg in args {
+                    arg.walk(visitor);
+                }
+                visitor.visit_apply(op, args);
+            }
+        }
+    }
+
+    /// Returns the number of nodes in the tree.
+    pub fn node_count(&self) -> usize {
+        match *self {
+            Expr::Apply(_, ref args) => 1 + args.iter().map(Expr::node_count).sum::<usize>(),
+            _ => 1,
+        }
+    }
+
+    /// Returns the number of nodes on the longest path from the root to a
+    /// leaf; a single number has depth 1.
+    pub fn depth(&self) -> usize {
+        match *self {
+            Expr::Apply(_, ref args) => 1 + args.iter().map(Expr::depth).fold(0, cmp::max),
+            _ => 1,
+        }
+    }
+}
+
+impl<N: Clone + fmt::Display> fmt::Display for Expr<N> {
+    /// Writes the expression in infix notation, like `to_infix`, or in
+    /// postfix notation if an operator has the wrong number of arguments.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tokens = self.to_postfix();
+        if let Ok(text) = to_infix(&tokens) {
+            return write!(f, "{}", text);
+        }
+        let words: Vec<String> = tokens.iter().map(|t| t.to_string()).collect();
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tokenize;
+    use super::*;
+
+    #[test]
+    fn test_expr() {
+        let tokens = tokenize("x dup * 1 +").unwrap();
+        let expr = Expr::from_postfix(&tokens).unwrap();
+        let x = || Expr::Variable("x".to_string());
+        assert_eq!(
+            expr,
+            Expr::Apply(
+                Operator::Add,
+                vec![Expr::Apply(Operator::Mul, vec![x(), x()]), Expr::Number(1)]
+            )
+        );
+        assert_eq!(expr.node_count(), 5);
+        assert_eq!(expr.depth(), 3);
+        assert_eq!(expr.to_postfix(), tokenize("x x * 1 +").unwrap());
+        assert_eq!(expr.to_string(), "x * x + 1");
+        assert_eq!(Expr::<isize>::from_postfix(&[]), Err(EvalError::Empty));
+
+        let malformed = Expr::Apply(Operator::Add, vec![Expr::Number(1)]);
+        assert_eq!(malformed.to_string(), "1 +");
+    }
+
+    #[test]
+    fn test_visitor() {
+        struct Names(Vec<String>);
+        impl Visitor<isize> for Names {
+            fn visit_variable(&mut self, name: &str) {
+                self.0.push(name.to_string());
+            }
+        }
+
+        let expr = Expr::from_postfix(&tokenize("a b + c *").unwrap()).unwrap();
+        let mut names = Names(Vec::new());
+        expr.walk(&mut names);
+        assert_eq!(names.0, vec!["a", "b", "c"]);
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt;
+
+use super::expr::fold_postfix;
+use super::{EvalError, Operator, Token};
+
+/// Binding strength of the forms an infix expression can take; higher binds
+/// tighter.
+const TERNARY: u8 = 0;
+const UNARY: u8 = 10;
+const ATOM: u8 = 12;
+
+/// How a chain of binary operators of equal precedence is grouped.
+#[derive(Clone, Copy, PartialEq)]
+enum Assoc {
+    Left,
+    Right,
+    /// Chains need parentheses, as for comparisons.
+    None,
+}
+
+/// Returns the precedence and grouping of a binary infix operator, or `None`
+/// for operators written in another form.
+fn binary(op: Operator) -> Option<(u8, Assoc)> {
+    match op {
+        Operator::Or => Some((1, Assoc::Left)),
+        Operator::And => Some((2, Assoc::Left)),
+        Operator::Lt | Operator::Le | Operator::Eq | Operator::Ne | Operator::Gt | Operator::Ge => {
+            Some((3, Assoc::None))
+        }
+        Operator::BitOr => Some((4, Assoc::Left)),
+        Operator::BitXor => Some((5, Assoc::Left)),
+        Operator::BitAnd => Some((6, Assoc::Left)),
+        Operator::Shl | Operator::Shr => Some((7, Assoc::Left)),
+        Operator::Add | Operator::Sub => Some((8, Assoc::Left)),
+        Operator::Mul | Operator::Div | Operator::Rem => Some((9, Assoc::Left)),
+        Operator::Pow => Some((11, Assoc::Right)),
+        Operator::Neg
+        | Operator::Abs
+        | Operator::Min
+        | Operator::Max
+        | Operator::Not
+        | Operator::Select => None,
+    }
+}
+
+/// Renders a postfix expression as infix text with as few parentheses as
+/// precedence and associativity allow.
+///
+/// Operators use their postfix symbols; `neg` is written as a prefix `-`,
+/// `?:` as `cond ? a : b`, `abs`, `min`, `max` and `not` as function calls.
+/// `**` groups to the right, and chained comparisons are parenthesized.
+/// Stack words are resolved, so `x dup *` is written `x * x`.
+pub fn to_infix<N: fmt::Display>(tokens: &[Token<N>]) -> Result<String, EvalError> {
+    // Every value is a rendered subexpression and the precedence of its
+    // outermost form.
+    let (text, _) = fold_postfix(
+        tokens,
+        |value| {
+            let text = value.to_string();
+            let prec = if text.starts_with('-') { UNARY } else { ATOM };
+            (text, prec)
+        },
+        |name| (name.to_string(), ATOM),
+        |_, op, args| Ok(render(op, args)),
+    )?;
+    Ok(text)
+}
+
+fn render(op: Operator, mut args: Vec<(String, u8)>) -> (String, u8) {
+    if let Some((prec, assoc)) = binary(op) {
+        let rhs = args.pop().unwrap();
+        let lhs = args.pop().unwrap();
+        // The side the operator groups towards may hold an operator of the
+        // same precedence without parentheses; the other side may not.
+        let (lhs_min, rhs_min) = match assoc {
+            Assoc::Left => (prec, prec + 1),
+            Assoc::Right => (prec + 1, prec),
+            Assoc::None => (prec + 1, prec + 1),
+        };
+        let text = format!(
+            "{} {} {}",
+            parenthesize(lhs, lhs_min),
+            op.symbol(),
+            parenthesize(rhs, rhs_min)
+        );
+        return (text, prec);
+    }
+    match op {
+        Operator::Neg => {
+            let arg = args.pop().unwrap();
+            (format!("-{}", parenthesize(arg, UNARY + 1)), UNARY)
+        }
+        Operator::Select => {
+            let otherwise = args.pop().unwrap();
+            let then = args.pop().unwrap();
+            let cond = args.pop().unwrap();
+            let text = format!(
+                "{} ? {} : {}",
+                parenthesize(cond, TERNARY + 1),
+                parenthesize(then, TERNARY + 1),
+                parenthesize(otherwise, TERNARY)
+            );
+            (text, TERNARY)
+        }
+        _ => {
+            let args: Vec<String> = args.into_iter().map(|(text, _)| text).collect();
+            (format!("{}({})", op.symbol(), args.join(", ")), ATOM)
+        }
+    }
+}
+
+/// Wraps `text` in parentheses if its precedence is below `min`.
+fn parenthesize((text, prec): (String, u8), min: u8) -> String {
+    if prec < min {
+        format!("({})", text)
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tokenize;
+    use super::*;
+
+    fn infix(source: &str) -> String {
+        to_infix(&tokenize(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_to_infix() {
+        assert_eq!(infix("3 4 - 5 -"), "3 - 4 - 5");
+        assert_eq!(infix("3 4 5 - -"), "3 - (4 - 5)");
+        assert_eq!(infix("3 4 + 5 *"), "(3 + 4) * 5");
+        assert_eq!(infix("3 4 5 * +"), "3 + 4 * 5");
+        assert_eq!(infix("2 3 ** 4 **"), "(2 ** 3) ** 4");
+        assert_eq!(infix("2 3 4 ** **"), "2 ** 3 ** 4");
+        assert_eq!(infix("-2 2 **"), "(-2) ** 2");
+        assert_eq!(infix("x neg neg 1 +"), "-(-x) + 1");
+        assert_eq!(infix("x dup * y 1 - abs min"), "min(x * x, abs(y - 1))");
+        assert_eq!(infix("a b < c d >= and e or"), "a < b and c >= d or e");
+        assert_eq!(infix("a b < c <"), "(a < b) < c");
+        assert_eq!(
+            infix("q 100 > q 10 * q ?: not"),
+            "not(q > 100 ? q * 10 : q)"
+        );
+        assert_eq!(infix("a b c d e ?: ?:"), "a ? b : c ? d : e");
+        assert_eq!(infix("a b c ?: d e ?:"), "(a ? b : c) ? d : e");
+    }
+
+    #[test]
+    fn test_to_infix_errors() {
+        assert_eq!(
+            to_infix(&tokenize("1 +").unwrap()),
+            Err(EvalError::StackUnderflow { index: 1, depth: 1 })
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use super::Number;
+
+/// A fixed-width integer type, for programmer-style evaluation of literals
+/// read by `tokenize_as` and results written by `to_radix`.
+///
+/// Hex (`0x`), binary (`0b`) and octal (`0o`) literals are bit patterns:
+/// anything that fits in `BITS` bits is accepted, so `0xff` is -1 as an
+/// `i8`. Decimal literals must be in range. Evaluate with
+/// `Overflow::Wrapping` for two's-complement wraparound.
+pub trait Integer: Number + Copy + fmt::Display {
+    /// Width of the type in bits.
+    const BITS: u32;
+
+    /// Returns the value whose bit pattern is the low `BITS` bits of `bits`.
+    fn from_bits(bits: u128) -> Self;
+    /// Returns the bit pattern of `self`, zero-extended to 128 bits.
+    fn to_bits(self) -> u128;
+    /// Returns `n` if it is in range.
+    fn from_i128(n: i128) -> Option<Self>;
+    /// Returns `n` if it is in range.
+    fn from_u128(n: u128) -> Option<Self>;
+
+    /// Returns the value of a literal with the given sign, magnitude and
+    /// radix, if it fits.
+    fn from_literal(negative: bool, magnitude: u128, radix: u32) -> Option<Self> {
+        if radix != 10 {
+            if Self::BITS < 128 && magnitude >> Self::BITS != 0 {
+                return None;
+            }
+            let bits = if negative {
+                magnitude.wrapping_neg()
+            } else {
+                magnitude
+            };
+            return Some(Self::from_bits(bits));
+        }
+        if negative {
+            Self::from_i128(0i128.checked_sub_unsigned(magnitude)?)
+        } else {
+            Self::from_u128(magnitude)
+        }
+    }
+}
+
+macro_rules! impl_integer_bits {
+    ($($t:ident as $unsigned:ident),*) => {$(
+        impl Integer for $t {
+            const BITS: u32 = $t::BITS;
+
+            fn from_bits(bits: u128) -> Self {
+                bits as $unsigned as $t
+            }
+
+            fn to_bits(self) -> u128 {
+                self as $unsigned as u128
+            }
+
+            fn from_i128(n: i128) -> Option<Self> {
+                $t::try_from(n).ok()
+            }
+
+            fn from_u128(n: u128) -> Option<Self> {
+                $t::try_from(n).ok()
+            }
+        }
+    )*};
+}
+
+impl_integer_bits! {
+    i8 as u8, i16 as u16, i32 as u32, i64 as u64, i128 as u128, isize as usize,
+    u8 as u8, u16 as u16, u32 as u32, u64 as u64, u128 as u128, usize as usize
+}
+
+/// Writes `value` in `radix`, which must be between 2 and 36.
+///
+/// Radix 10 gives the signed decimal value; the others give the bit pattern,
+/// so -1 as an `i8` is `0xff`. Hex, binary and octal get the prefix that
+/// `tokenize_as` reads back.
+pub fn to_radix<N: Integer>(value: N, radix: u32) -> String {
+    assert!((2..=36).contains(&radix), "radix {} out of range", radix);
+    if radix == 10 {
+        return value.to_string();
+    }
+    let prefix = match radix {
+        2 => "0b",
+        8 => "0o",
+        16 => "0x",
+        _ => "",
+    };
+    let mut bits = value.to_bits();
+    let mut digits = Vec::new();
+    loop {
+        digits.push(std::char::from_digit((bits % radix as u128) as u32, radix).unwrap());
+        bits /= radix as u128;
+        if bits == 0 {
+            break;
+        }
+    }
+    prefix.chars().chain(digits.into_iter().rev()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{
+        eval, eval_with_options, tokenize_as, Division, EvalError, EvalOptions, LexErrorKind,
+        LexLimits, Overflow,
+    };
+    use super::*;
+
+    #[test]
+    fn test_programmer_mode() {
+        let lex = |source| tokenize_as::<u8>(source, &LexLimits::default());
+        let wrapping = EvalOptions::new(Overflow::Wrapping, Division::Truncating);
+
+        assert_eq!(eval(&lex("0xf0 0b1010 | 0o7 &").unwrap()), Ok(2));
+        let sum = lex("250 0x0A +").unwrap();
+        assert_eq!(eval(&sum), Err(EvalError::Overflow { index: 2 }));
+        assert_eq!(eval_with_options(&sum, &wrapping), Ok(4));
+        assert_eq!(eval(&lex("-0x1").unwrap()), Ok(255));
+        assert_eq!(
+            lex("256").unwrap_err().kind,
+            LexErrorKind::LiteralOutOfRange
+        );
+        assert_eq!(
+            lex("0x1_00").unwrap_err().kind,
+            LexErrorKind::LiteralOutOfRange
+        );
+
+        let tokens = tokenize_as::<i8>("0x7f 1 +", &LexLimits::default()).unwrap();
+        assert_eq!(eval_with_options(&tokens, &wrapping), Ok(-128));
+        let tokens = tokenize_as::<i8>("0xff", &LexLimits::default()).unwrap();
+        assert_eq!(eval(&tokens), Ok(-1));
+
+        let tokens = tokenize_as::<u128>("18446744073709551616", &LexLimits::default()).unwrap();
+        assert_eq!(eval(&tokens), Ok(1 << 64));
+        let tokens = tokenize_as::<i128>(&format!("0x{:x}", u128::MAX), &LexLimits::default());
+        assert_eq!(eval(&tokens.unwrap()), Ok(-1));
+    }
+
+    #[test]
+    fn test_to_radix() {
+        assert_eq!(to_radix(-1i8, 16), "0xff");
+        assert_eq!(to_radix(10u32, 2), "0b1010");
+        assert_eq!(to_radix(-5i16, 10), "-5");
+        assert_eq!(to_radix(0o755u16, 8), "0o755");
+        assert_eq!(to_radix(35u64, 36), "z");
+        assert_eq!(to_radix(0i64, 16), "0x0");
+    }
+}
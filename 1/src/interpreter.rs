@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+
+use super::machine::Machine;
+use super::{Environment, EvalError, EvalOptions, NoVariables, Number, Token};
+
+/// How deeply user-defined words may call each other unless changed with
+/// `Interpreter::set_max_call_depth`.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 64;
+
+/// A postfix evaluator with a dictionary of user-defined words that persists
+/// across calls to `eval`.
+///
+/// A definition `: name body ;` may appear anywhere in the input. The rules
+/// for words are:
+/// * Words are looked up when they are called, not when they are defined, so
+///   a word may call itself or a word defined later.
+/// * Defining an existing word replaces it, also inside words that call it.
+/// * Operators and stack words cannot be redefined, since they never lex as
+///   names.
+/// * A word shadows a variable of the same name.
+/// * Calls nest at most `max_call_depth` deep; deeper calls (usually runaway
+///   recursion) fail with `EvalError::CallDepth`.
+///
+/// Errors raised inside a word report the index of the call in the input.
+pub struct Interpreter<N = isize> {
+    words: HashMap<String, Vec<Token<N>>>,
+    /// Total number of tokens in the bodies of `words`.
+    stored: usize,
+    options: EvalOptions,
+    max_call_depth: usize,
+}
+
+impl<N: Number> Interpreter<N> {
+    /// Creates an interpreter with no words and the default options.
+    pub fn new() -> Self {
+        Interpreter::with_options(EvalOptions::default())
+    }
+
+    /// Creates an interpreter with no words that computes with `options`.
+    pub fn with_options(options: EvalOptions) -> Self {
+        Interpreter {
+            words: HashMap::new(),
+            stored: 0,
+            options,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+        }
+    }
+
+    /// Sets how deeply words may call each other.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    /// Defines (or redefines) the word `name` as `body`. Fails if `body`
+    /// itself contains a definition, or if the bodies of all words would
+    /// exceed `Limits::max_word_tokens`; the error indexes into `body`.
+    pub fn define(&mut self, name: &str, body: &[Token<N>]) -> Result<(), EvalError> {
+        if let Some(index) = body.iter().position(is_definition_token) {
+            return Err(EvalError::Definition { index });
+        }
+        self.store(name.to_string(), body.to_vec(), 0)
+    }
+
+    /// Removes the word `name`. Returns `false` if it was not defined.
+    pub fn forget(&mut self, name: &str) -> bool {
+        match self.words.remove(name) {
+            Some(body) => {
+                self.stored -= body.len();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns `true` if `name` is a user-defined word.
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.words.contains_key(name)
+    }
+
+    /// Records the definitions in `tokens` and evaluates the rest.
+    ///
+    /// Returns `Ok(None)` if `tokens` consists of definitions only. The
+    /// definitions are kept even if the rest fails to evaluate.
+    pub fn eval(&mut self, tokens: &[Token<N>]) -> Result<Option<N>, EvalError> {
+        self.eval_with(tokens, &NoVariables)
+    }
+
+    /// Like `eval`, but looks up names that are not words in `env`.
+    pub fn eval_with<E: Environment<N> + ?Sized>(
+        &mut self,
+        tokens: &[Token<N>],
+        env: &E,
+    ) -> Result<Option<N>, EvalError> {
+        let max_tokens = self.options.limits.max_tokens;
+        if tokens.len() > max_tokens {
+            return Err(EvalError::TooManyTokens { index: max_tokens });
+        }
+        let (definitions, code) = split_definitions(tokens)?;
+        let defined_any = !definitions.is_empty();
+        for (name, body, start) in definitions {
+            self.store(name, body, start)?;
+        }
+        if code.is_empty() && defined_any {
+            return Ok(None);
+        }
+
+        let mut machine = Machine::new();
+        for (index, token) in code {
+            self.exec(&mut machine, token, index, env, 0)?;
+        }
+        machine.finish().map(Some)
+    }
+
+    /// Stores `body`, which starts at token `start` of the input, as the word
+    /// `name`, unless the bodies of all words would then exceed
+    /// `Limits::max_word_tokens`.
+    fn store(&mut self, name: String, body: Vec<Token<N>>, start: usize) -> Result<(), EvalError> {
+        let replaced = self.words.get(&name).map_or(0, Vec::len);
+        let room = self.options.limits.max_word_tokens - (self.stored - replaced);
+        if body.len() > room {
+            return Err(EvalError::TooManyTokens {
+                index: start + room,
+            });
+        }
+        self.stored = self.stored - replaced + body.len();
+        self.words.insert(name, body);
+        Ok(())
+    }
+
+    /// Executes `token`, expanding calls to words `depth` levels deep.
+    fn exec<E: Environment<N> + ?Sized>(
+        &self,
+        machine: &mut Machine<N>,
+        token: &Token<N>,
+        index: usize,
+        env: &E,
+        depth: usize,
+    ) -> Result<(), EvalError> {
+        if let Token::Variable(ref name) = *token {
+            if let Some(body) = self.words.get(name) {
+                if depth >= self.max_call_depth {
+                    return Err(EvalError::CallDepth { index });
+                }
+                machine.charge_step(index, &self.options)?;
+                for token in body {
+                    self.exec(machine, token, index, env, depth + 1)?;
+                }
+                return Ok(());
+            }
+        }
+        machine.step(token, index, env, &self.options)
+    }
+}
+
+impl<N: Number> Default for Interpreter<N> {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+fn is_definition_token<N>(token: &Token<N>) -> bool {
+    matches!(*token, Token::Define | Token::End)
+}
+
+/// The name and body of a word, and the index of the first body token.
+type Definition<N> = (String, Vec<Token<N>>, usize);
+type Code<'a, N> = Vec<(usize, &'a Token<N>)>;
+
+/// Separates `: name body ;` definitions from the code to evaluate, which is
+/// returned with the index of every token.
+fn split_definitions<N: Clone>(
+    tokens: &[Token<N>],
+) -> Result<(Vec<Definition<N>>, Code<'_, N>), EvalError> {
+    let mut definitions = Vec::new();
+    let mut code = Vec::new();
+    let mut iter = tokens.iter().enumerate();
+
+    while let Some((index, token)) = iter.next() {
+        match *token {
+            Token::Define => {
+                let name = match iter.next() {
+                    Some((_, Token::Variable(name))) => name.clone(),
+                    _ => return Err(EvalError::Definition { index }),
+                };
+                let mut body = Vec::new();
+                let start = index + 2;
+                loop {
+                    match iter.next() {
+                        Some((_, &Token::End)) => break,
+                        Some((i, &Token::Define)) => {
+                            return Err(EvalError::Definition { index: i })
+                        }
+                        Some((_, token)) => body.push(token.clone()),
+                        None => return Err(EvalError::Definition { index }),
+                    }
+                }
+                definitions.push((name, body, start));
+            }
+            Token::End => return Err(EvalError::Definition { index }),
+            _ => code.push((index, token)),
+        }
+    }
+    Ok((definitions, code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tokenize;
+    use super::*;
+
+    #[test]
+    fn test_words() {
+        let mut interp = Interpreter::new();
+        assert_eq!(interp.eval(&tokenize(": sq dup * ;").unwrap()), Ok(None));
+        assert!(interp.is_defined("sq"));
+        assert_eq!(interp.eval(&tokenize("3 sq").unwrap()), Ok(Some(9)));
+        assert_eq!(
+            interp.eval(&tokenize(": quad sq sq ; 2 quad").unwrap()),
+            Ok(Some(16))
+        );
+
+        // Redefinition is seen by words that call it.
+        interp.eval(&tokenize(": sq dup + ;").unwrap()).unwrap();
+        assert_eq!(interp.eval(&tokenize("2 quad").unwrap()), Ok(Some(8)));
+
+        assert!(interp.forget("sq"));
+        assert_eq!(
+            interp.eval(&tokenize("2 quad").unwrap()),
+            Err(EvalError::UnboundVariable { index: 1 })
+        );
+    }
+
+    #[test]
+    fn test_word_errors() {
+        let mut interp: Interpreter = Interpreter::new();
+        assert_eq!(
+            interp.eval(&tokenize(": loop loop ; loop").unwrap()),
+            Err(EvalError::CallDepth { index: 4 })
+        );
+        assert_eq!(
+            interp.eval(&tokenize("1 : dup 2 ;").unwrap()),
+            Err(EvalError::Definition { index: 1 })
+        );
+        assert_eq!(
+            interp.eval(&tokenize(": a 1 : b ; ;").unwrap()),
+            Err(EvalError::Definition { index: 3 })
+        );
+        assert_eq!(
+            interp.eval(&tokenize(": a 1").unwrap()),
+            Err(EvalError::Definition { index: 0 })
+        );
+        assert_eq!(
+            interp.eval(&tokenize("1 ;").unwrap()),
+            Err(EvalError::Definition { index: 1 })
+        );
+    }
+}
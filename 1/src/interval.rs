@@ -0,0 +1,303 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use super::{eval_with, ArithError, Environment, EvalError, EvalOptions, Number, Token};
+
+/// A closed range `[lo, hi]` of `f64` values, for evaluation with guaranteed
+/// bounds.
+///
+/// Every operation returns an interval that contains the result for every
+/// choice of operands within the operand intervals. Bounds are rounded
+/// outwards by one step after each operation that may round, so floating-point
+/// rounding never makes them too narrow. Bounds may become infinite.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interval {
+    lo: f64,
+    hi: f64,
+}
+
+impl Interval {
+    /// Creates the interval `[lo, hi]`. Fails with `ArithError::Domain` if a
+    /// bound is NaN or `lo > hi`.
+    pub fn new(lo: f64, hi: f64) -> Result<Interval, ArithError> {
+        if lo <= hi {
+            Ok(Interval { lo, hi })
+        } else {
+            Err(ArithError::Domain)
+        }
+    }
+
+    /// Creates the interval that contains only `x`.
+    pub fn point(x: f64) -> Interval {
+        Interval { lo: x, hi: x }
+    }
+
+    pub fn lo(&self) -> f64 {
+        self.lo
+    }
+
+    pub fn hi(&self) -> f64 {
+        self.hi
+    }
+
+    /// Returns `hi - lo`, rounded up if it is not exact, so a point has
+    /// width 0.
+    pub fn width(&self) -> f64 {
+        let (a, b) = (self.hi, -self.lo);
+        let width = a + b;
+        // Knuth's two-sum: `error` is what rounding `a + b` lost.
+        let rounded_b = width - a;
+        let error = (a - (width - rounded_b)) + (b - rounded_b);
+        if error > 0.0 {
+            width.next_up()
+        } else {
+            width
+        }
+    }
+
+    /// Returns `true` if `x` lies within the interval.
+    pub fn contains(&self, x: f64) -> bool {
+        self.lo <= x && x <= self.hi
+    }
+
+    /// Returns `[lo, hi]` with both bounds moved one step outwards, to cover
+    /// the rounding error of the operation that computed them.
+    fn outward(lo: f64, hi: f64) -> Result<Interval, ArithError> {
+        Interval::new(lo.next_down(), hi.next_up())
+    }
+}
+
+/// The smallest interval containing `n`, which is wider than a point only if
+/// `n` has no exact `f64` representation.
+impl From<isize> for Interval {
+    fn from(n: isize) -> Interval {
+        let x = n as f64;
+        match (x as i128).cmp(&(n as i128)) {
+            Ordering::Less => Interval {
+                lo: x,
+                hi: x.next_up(),
+            },
+            Ordering::Equal => Interval::point(x),
+            Ordering::Greater => Interval {
+                lo: x.next_down(),
+                hi: x,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Interval {
+    /// Writes `[lo, hi]`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}, {}]", self.lo, self.hi)
+    }
+}
+
+/// Returns `x ** n` for `x >= 0`, rounded down or up at every step.
+fn pow_bound(x: f64, mut n: u32, up: bool) -> f64 {
+    let round = |y: f64| {
+        if up {
+            y.next_up()
+        } else {
+            y.next_down().max(0.0)
+        }
+    };
+    let (mut base, mut result) = (x, 1.0);
+    while n > 0 {
+        if n & 1 == 1 {
+            result = round(result * base);
+        }
+        base = round(base * base);
+        n >>= 1;
+    }
+    result
+}
+
+/// `options` are ignored: overflow yields an infinite bound, which is still
+/// sound. Division by an interval that contains zero is a `DivisionByZero`.
+/// `**` needs an exponent that is a single integer. Comparisons fail with
+/// `ArithError::Domain` unless every pair of values compares the same way.
+impl Number for Interval {
+    fn zero() -> Self {
+        Interval::point(0.0)
+    }
+
+    fn one() -> Self {
+        Interval::point(1.0)
+    }
+
+    fn add(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Interval::outward(self.lo + rhs.lo, self.hi + rhs.hi)
+    }
+
+    fn sub(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Interval::outward(self.lo - rhs.hi, self.hi - rhs.lo)
+    }
+
+    fn mul(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        let products = [
+            self.lo * rhs.lo,
+            self.lo * rhs.hi,
+            self.hi * rhs.lo,
+            self.hi * rhs.hi,
+        ];
+        let lo = products.iter().cloned().fold(f64::INFINITY, f64::min);
+        let hi = products.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        Interval::outward(lo, hi)
+    }
+
+    fn div(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+        if rhs.contains(0.0) {
+            return Err(ArithError::DivisionByZero);
+        }
+        let reciprocal = Interval::outward(1.0 / rhs.hi, 1.0 / rhs.lo)?;
+        self.mul(&reciprocal, options)
+    }
+
+    fn pow(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+        let n = rhs.lo;
+        if rhs.hi != n || n.fract() != 0.0 || n.abs() > u32::MAX as f64 {
+            return Err(ArithError::Domain);
+        }
+        if n < 0.0 {
+            let positive = self.pow(&Interval::point(-n), options)?;
+            return Interval::one().div(&positive, options);
+        }
+        let n = n as u32;
+        // `x ** n` is monotonic on either side of zero.
+        let (lo_abs, hi_abs) = (self.lo.abs(), self.hi.abs());
+        let result = if n % 2 == 1 {
+            let bound = |x: f64, up: bool| {
+                if x < 0.0 {
+                    -pow_bound(-x, n, !up)
+                } else {
+                    pow_bound(x, n, up)
+                }
+            };
+            Interval {
+                lo: bound(self.lo, false),
+                hi: bound(self.hi, true),
+            }
+        } else if self.contains(0.0) {
+            Interval {
+                lo: if n == 0 { 1.0 } else { 0.0 },
+                hi: pow_bound(lo_abs.max(hi_abs), n, true),
+            }
+        } else {
+            Interval {
+                lo: pow_bound(lo_abs.min(hi_abs), n, false),
+                hi: pow_bound(lo_abs.max(hi_abs), n, true),
+            }
+        };
+        Ok(result)
+    }
+
+    fn neg(&self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Ok(Interval {
+            lo: -self.hi,
+            hi: -self.lo,
+        })
+    }
+
+    fn abs(&self, options: &EvalOptions) -> Result<Self, ArithError> {
+        if self.lo >= 0.0 {
+            Ok(*self)
+        } else if self.hi <= 0.0 {
+            self.neg(options)
+        } else {
+            Ok(Interval {
+                lo: 0.0,
+                hi: self.hi.max(-self.lo),
+            })
+        }
+    }
+
+    fn min(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Ok(Interval {
+            lo: self.lo.min(rhs.lo),
+            hi: self.hi.min(rhs.hi),
+        })
+    }
+
+    fn max(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Ok(Interval {
+            lo: self.lo.max(rhs.lo),
+            hi: self.hi.max(rhs.hi),
+        })
+    }
+
+    fn compare(&self, rhs: &Self) -> Result<Ordering, ArithError> {
+        if self.hi < rhs.lo {
+            Ok(Ordering::Less)
+        } else if self.lo > rhs.hi {
+            Ok(Ordering::Greater)
+        } else if self.lo == self.hi && rhs.lo == rhs.hi {
+            Ok(Ordering::Equal)
+        } else {
+            Err(ArithError::Domain)
+        }
+    }
+
+    fn signed_zero() -> bool {
+        true
+    }
+}
+
+/// Evaluates `tokens` with every operand widened to an interval and the
+/// variables from `env`, and returns an interval that is guaranteed to
+/// contain the result for all values within the variables' intervals.
+pub fn eval_interval<E: Environment<Interval> + ?Sized>(
+    tokens: &[Token],
+    env: &E,
+) -> Result<Interval, EvalError> {
+    let tokens: Vec<Token<Interval>> = tokens.iter().map(|t| t.map(|&n| n.into())).collect();
+    eval_with(&tokens, env)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tokenize;
+    use super::*;
+
+    fn bounds(source: &str, x: Interval) -> Result<Interval, EvalError> {
+        eval_interval(&tokenize(source).unwrap(), &[("x", x)][..])
+    }
+
+    #[test]
+    fn test_eval_interval() {
+        let x = Interval::new(-1.0, 2.0).unwrap();
+        let sum = bounds("x 10 +", x).unwrap();
+        assert!(sum.contains(9.0) && sum.contains(12.0));
+        assert!(sum.width() < 3.0 + 1e-12);
+        assert_eq!(Interval::point(5.0).width(), 0.0);
+        assert_eq!(x.width(), 3.0);
+        let tiny = Interval::new(-1e-300, 1.0).unwrap();
+        assert!(tiny.width() > 1.0);
+
+        // Each factor varies on its own, so the product is wider than x ** 2.
+        let product = bounds("x x *", x).unwrap();
+        assert!(product.contains(-2.0) && product.contains(4.0));
+        let square = bounds("x 2 **", x).unwrap();
+        assert_eq!(square.lo(), 0.0);
+        assert!(square.contains(4.0) && square.hi() < 4.0 + 1e-12);
+
+        let third = bounds("1 x /", Interval::new(3.0, 3.0).unwrap()).unwrap();
+        assert!(third.contains(1.0 / 3.0) && third.lo() < third.hi());
+
+        let reading = Interval::new(1.0, 2.0).unwrap();
+        assert_eq!(bounds("x 5 < 7 9 ?:", reading), Ok(Interval::point(7.0)));
+        assert_eq!(Interval::from(isize::MAX).hi(), isize::MAX as f64);
+        assert!(Interval::from(isize::MAX).contains(isize::MAX as f64));
+    }
+
+    #[test]
+    fn test_eval_interval_errors() {
+        let x = Interval::new(-1.0, 2.0).unwrap();
+        assert_eq!(
+            bounds("1 x /", x),
+            Err(EvalError::DivisionByZero { index: 2 })
+        );
+        assert_eq!(bounds("x 1 <", x), Err(EvalError::Domain { index: 2 }));
+        assert_eq!(Interval::new(2.0, 1.0), Err(ArithError::Domain));
+    }
+}
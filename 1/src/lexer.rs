@@ -0,0 +1,255 @@
+use std::ops::Range;
+use std::{error, fmt};
+
+use super::{Integer, Operator, StackOp, Token};
+
+/// Describes why a piece of source text could not be tokenized.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    /// Byte range of the offending word in the source text.
+    pub span: Range<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LexErrorKind {
+    /// The word is not an operator, a stack word, `:`, `;`, a literal or a
+    /// variable name.
+    UnknownSymbol,
+    /// The word is an integer literal that does not fit in the operand type,
+    /// or is larger than `LexLimits::max_literal`.
+    LiteralOutOfRange,
+    /// The word is beyond `LexLimits::max_tokens`.
+    TooManyTokens,
+}
+
+/// Bounds on the source text accepted by `tokenize_with_limits` and
+/// `tokenize_as`, for reading untrusted input. Both default to unlimited.
+///
+/// These are checked while lexing only; the limits that apply during
+/// evaluation are in `Limits`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LexLimits {
+    /// Most tokens the source may consist of.
+    pub max_tokens: usize,
+    /// Largest absolute value of a literal, or `None` for no limit beyond
+    /// the range of the operand type.
+    pub max_literal: Option<u128>,
+}
+
+impl Default for LexLimits {
+    fn default() -> Self {
+        LexLimits {
+            max_tokens: usize::MAX,
+            max_literal: None,
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let what = match self.kind {
+            LexErrorKind::UnknownSymbol => "unknown symbol",
+            LexErrorKind::LiteralOutOfRange => "literal out of range",
+            LexErrorKind::TooManyTokens => "too many tokens",
+        };
+        write!(f, "{} at {}..{}", what, self.span.start, self.span.end)
+    }
+}
+
+impl error::Error for LexError {}
+
+/// Splits `source` into postfix tokens.
+///
+/// Words are separated by any amount of whitespace. A `#` at the start of a
+/// word comments out the rest of the line. A `-` directly followed by digits
+/// is a negative literal; on its own it is the subtraction operator. Literals
+/// may also be written in hex (`0xff`), binary (`0b101`) or octal (`0o17`),
+/// and may contain `_` between digits. Words that look like identifiers and
+/// are not operators or stack words become variables.
+pub fn tokenize(source: &str) -> Result<Vec<Token>, LexError> {
+    tokenize_as(source, &LexLimits::default())
+}
+
+/// Splits `source` into postfix tokens like `tokenize`, rejecting more than
+/// `limits.max_tokens` tokens and literals larger than `limits.max_literal`.
+pub fn tokenize_with_limits(source: &str, limits: &LexLimits) -> Result<Vec<Token>, LexError> {
+    tokenize_as(source, limits)
+}
+
+/// Splits `source` into postfix tokens with operands of type `N`, within
+/// `limits`. Hex, binary and octal literals are bit patterns; see `Integer`.
+pub fn tokenize_as<N: Integer>(
+    source: &str,
+    limits: &LexLimits,
+) -> Result<Vec<Token<N>>, LexError> {
+    let mut tokens = Vec::new();
+    for (span, word) in words(source) {
+        if tokens.len() == limits.max_tokens {
+            return Err(LexError {
+                kind: LexErrorKind::TooManyTokens,
+                span,
+            });
+        }
+        tokens.push(token(word, span, limits.max_literal)?);
+    }
+    Ok(tokens)
+}
+
+/// Returns every word of `source` that is not part of a comment, together
+/// with its byte range.
+fn words(source: &str) -> Vec<(Range<usize>, &str)> {
+    let mut result = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut in_comment = false;
+
+    for (i, c) in source.char_indices() {
+        if in_comment {
+            in_comment = c != '\n';
+        } else if c.is_whitespace() {
+            if let Some(s) = start.take() {
+                result.push((s..i, &source[s..i]));
+            }
+        } else if start.is_none() {
+            if c == '#' {
+                in_comment = true;
+            } else {
+                start = Some(i);
+            }
+        }
+    }
+    if let Some(s) = start {
+        result.push((s..source.len(), &source[s..]));
+    }
+    result
+}
+
+fn token<N: Integer>(
+    word: &str,
+    span: Range<usize>,
+    max_literal: Option<u128>,
+) -> Result<Token<N>, LexError> {
+    if let Some(op) = Operator::from_symbol(word) {
+        return Ok(Token::Operator(op));
+    }
+    if let Some(op) = StackOp::from_word(word) {
+        return Ok(Token::Stack(op));
+    }
+    match word {
+        ":" => return Ok(Token::Define),
+        ";" => return Ok(Token::End),
+        _ => {}
+    }
+    if is_identifier(word) {
+        return Ok(Token::Variable(word.to_string()));
+    }
+
+    let (negative, magnitude, radix) = match literal(word) {
+        Some(literal) => literal,
+        None => {
+            return Err(LexError {
+                kind: LexErrorKind::UnknownSymbol,
+                span,
+            })
+        }
+    };
+    match magnitude {
+        Some(m) if max_literal.is_none_or(|max| m <= max) => N::from_literal(negative, m, radix),
+        _ => None,
+    }
+    .map(Token::Operand)
+    .ok_or(LexError {
+        kind: LexErrorKind::LiteralOutOfRange,
+        span,
+    })
+}
+
+/// Splits an integer literal into its sign, magnitude and radix, or returns
+/// `None` if `word` is not one. The magnitude is `None` if it does not fit in
+/// `u128`.
+fn literal(word: &str) -> Option<(bool, Option<u128>, u32)> {
+    let (negative, unsigned) = match word.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, word.strip_prefix('+').unwrap_or(word)),
+    };
+    let (radix, digits) = match unsigned.get(..2) {
+        Some("0x") => (16, &unsigned[2..]),
+        Some("0b") => (2, &unsigned[2..]),
+        Some("0o") => (8, &unsigned[2..]),
+        _ => (10, unsigned),
+    };
+    if !digits.starts_with(|c: char| c.is_digit(radix))
+        || !digits.chars().all(|c| c == '_' || c.is_digit(radix))
+    {
+        return None;
+    }
+    let magnitude = u128::from_str_radix(&digits.replace('_', ""), radix).ok();
+    Some((negative, magnitude, radix))
+}
+
+/// Returns `true` for words made of letters, digits and `_` that do not start
+/// with a digit.
+fn is_identifier(word: &str) -> bool {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("3 -4\t+\n  2 * # times two\n-").unwrap(),
+            vec![
+                Token::Operand(3),
+                Token::Operand(-4),
+                Token::Operator(Operator::Add),
+                Token::Operand(2),
+                Token::Operator(Operator::Mul),
+                Token::Operator(Operator::Sub),
+            ]
+        );
+        assert_eq!(tokenize("  # nothing here").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_tokenize_errors() {
+        assert_eq!(
+            tokenize("1 2 $ x").unwrap_err(),
+            LexError {
+                kind: LexErrorKind::UnknownSymbol,
+                span: 4..5
+            }
+        );
+        assert_eq!(
+            tokenize("1 99999999999999999999999 +").unwrap_err(),
+            LexError {
+                kind: LexErrorKind::LiteralOutOfRange,
+                span: 2..25
+            }
+        );
+
+        let limits = LexLimits {
+            max_tokens: 3,
+            max_literal: Some(100),
+        };
+        assert!(tokenize_with_limits("-100 100 +", &limits).is_ok());
+        assert_eq!(
+            tokenize_with_limits("1 -101 +", &limits).unwrap_err().kind,
+            LexErrorKind::LiteralOutOfRange
+        );
+        assert_eq!(
+            tokenize_with_limits("1 2 + 3", &limits).unwrap_err(),
+            LexError {
+                kind: LexErrorKind::TooManyTokens,
+                span: 6..7
+            }
+        );
+    }
+}
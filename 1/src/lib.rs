@@ -1,3 +1,48 @@
+use std::cmp::{self, Ordering};
+use std::{error, fmt};
+
+use machine::Machine;
+
+mod batch;
+mod derive;
+mod dual;
+mod env;
+mod evaluator;
+mod expr;
+mod infix;
+mod integer;
+mod interpreter;
+mod interval;
+mod lexer;
+mod machine;
+mod modular;
+mod number;
+mod optimize;
+mod options;
+mod program;
+mod rational;
+mod trace;
+
+pub use batch::{eval_batch, eval_line, LineError};
+pub use derive::derive;
+pub use dual::{eval_gradient, Dual};
+pub use env::{Environment, NoVariables};
+pub use evaluator::Evaluator;
+pub use expr::{Expr, Visitor};
+pub use infix::to_infix;
+pub use integer::{to_radix, Integer};
+pub use interpreter::{Interpreter, DEFAULT_MAX_CALL_DEPTH};
+pub use interval::{eval_interval, Interval};
+pub use lexer::{tokenize, tokenize_as, tokenize_with_limits, LexError, LexErrorKind, LexLimits};
+pub use modular::{eval_mod, Modular};
+pub use number::{ArithError, Number};
+pub use optimize::{optimize, optimize_assuming_bound, optimize_with_options};
+pub use options::{Division, EvalOptions, Limits, Overflow};
+pub use program::{compile, Program};
+pub use rational::{eval_exact, Rational};
+pub use trace::{trace, trace_with, Step, Trace};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Operator {
     // `+`
     Add,
@@ -7,97 +52,809 @@ pub enum Operator {
     Mul,
     // '/'
     Div,
+    // `%`
+    Rem,
+    // `**`
+    Pow,
+    // `neg`
+    Neg,
+    // `abs`
+    Abs,
+    // `min`
+    Min,
+    // `max`
+    Max,
+    // `&`
+    BitAnd,
+    // `|`
+    BitOr,
+    // `^`
+    BitXor,
+    // `<<`
+    Shl,
+    // `>>`
+    Shr,
+    // `<`
+    Lt,
+    // `<=`
+    Le,
+    // `==`
+    Eq,
+    // `!=`
+    Ne,
+    // `>`
+    Gt,
+    // `>=`
+    Ge,
+    // `and`
+    And,
+    // `or`
+    Or,
+    // `not`
+    Not,
+    // `?:`: ( cond a b -- cond ? a : b )
+    Select,
 }
 
-pub enum Token {
+/// Every operator, in declaration order.
+pub const OPERATORS: [Operator; 25] = [
+    Operator::Add,
+    Operator::Sub,
+    Operator::Mul,
+    Operator::Div,
+    Operator::Rem,
+    Operator::Pow,
+    Operator::Neg,
+    Operator::Abs,
+    Operator::Min,
+    Operator::Max,
+    Operator::BitAnd,
+    Operator::BitOr,
+    Operator::BitXor,
+    Operator::Shl,
+    Operator::Shr,
+    Operator::Lt,
+    Operator::Le,
+    Operator::Eq,
+    Operator::Ne,
+    Operator::Gt,
+    Operator::Ge,
+    Operator::And,
+    Operator::Or,
+    Operator::Not,
+    Operator::Select,
+];
+
+impl Operator {
+    /// Returns the number of operands the operator pops off the stack. It
+    /// always pushes exactly one result.
+    pub fn arity(self) -> usize {
+        match self {
+            Operator::Neg | Operator::Abs | Operator::Not => 1,
+            Operator::Select => 3,
+            _ => 2,
+        }
+    }
+
+    /// Returns the word that spells the operator in source text.
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Operator::Add => "+",
+            Operator::Sub => "-",
+            Operator::Mul => "*",
+            Operator::Div => "/",
+            Operator::Rem => "%",
+            Operator::Pow => "**",
+            Operator::Neg => "neg",
+            Operator::Abs => "abs",
+            Operator::Min => "min",
+            Operator::Max => "max",
+            Operator::BitAnd => "&",
+            Operator::BitOr => "|",
+            Operator::BitXor => "^",
+            Operator::Shl => "<<",
+            Operator::Shr => ">>",
+            Operator::Lt => "<",
+            Operator::Le => "<=",
+            Operator::Eq => "==",
+            Operator::Ne => "!=",
+            Operator::Gt => ">",
+            Operator::Ge => ">=",
+            Operator::And => "and",
+            Operator::Or => "or",
+            Operator::Not => "not",
+            Operator::Select => "?:",
+        }
+    }
+
+    /// Returns the operator spelled `symbol`, if any.
+    pub fn from_symbol(symbol: &str) -> Option<Operator> {
+        OPERATORS.iter().cloned().find(|op| op.symbol() == symbol)
+    }
+
+    /// Applies the operator to `args`, the topmost `self.arity()` stack
+    /// values in push order. Panics if `args` has the wrong length.
+    ///
+    /// Comparisons and logical operators yield 1 for true and 0 for false;
+    /// any value other than 0 counts as true.
+    pub fn apply<N: Number>(self, args: &[N], options: &EvalOptions) -> Result<N, ArithError> {
+        assert_eq!(args.len(), self.arity());
+        let (x, y) = (&args[0], args.last().unwrap());
+        let is_true = |value: &N| Ok(value.compare(&N::zero())? != Ordering::Equal);
+        let truth = |b: bool| Ok(if b { N::one() } else { N::zero() });
+        match self {
+            Operator::Add => x.add(y, options),
+            Operator::Sub => x.sub(y, options),
+            Operator::Mul => x.mul(y, options),
+            Operator::Div => x.div(y, options),
+            Operator::Rem => x.rem(y, options),
+            Operator::Pow => x.pow(y, options),
+            Operator::Neg => x.neg(options),
+            Operator::Abs => x.abs(options),
+            Operator::Min => x.min(y, options),
+            Operator::Max => x.max(y, options),
+            Operator::BitAnd => x.bitand(y, options),
+            Operator::BitOr => x.bitor(y, options),
+            Operator::BitXor => x.bitxor(y, options),
+            Operator::Shl => x.shl(y, options),
+            Operator::Shr => x.shr(y, options),
+            Operator::Lt => truth(x.compare(y)? == Ordering::Less),
+            Operator::Le => truth(x.compare(y)? != Ordering::Greater),
+            Operator::Eq => truth(x.compare(y)? == Ordering::Equal),
+            Operator::Ne => truth(x.compare(y)? != Ordering::Equal),
+            Operator::Gt => truth(x.compare(y)? == Ordering::Greater),
+            Operator::Ge => truth(x.compare(y)? != Ordering::Less),
+            Operator::And => truth(is_true(x)? & is_true(y)?),
+            Operator::Or => truth(is_true(x)? | is_true(y)?),
+            Operator::Not => truth(!is_true(x)?),
+            Operator::Select => Ok(if is_true(x)? {
+                args[1].clone()
+            } else {
+                y.clone()
+            }),
+        }
+    }
+}
+
+/// A Forth-style word that rearranges the stack without computing anything.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StackOp {
+    // `dup`: ( a -- a a )
+    Dup,
+    // `swap`: ( a b -- b a )
+    Swap,
+    // `drop`: ( a -- )
+    Drop,
+    // `over`: ( a b -- a b a )
+    Over,
+    // `rot`: ( a b c -- b c a )
+    Rot,
+}
+
+/// Every stack word, in declaration order.
+pub const STACK_OPS: [StackOp; 5] = [
+    StackOp::Dup,
+    StackOp::Swap,
+    StackOp::Drop,
+    StackOp::Over,
+    StackOp::Rot,
+];
+
+impl StackOp {
+    /// Returns how many values the word needs on the stack and how many it
+    /// leaves in their place.
+    pub fn effect(self) -> (usize, usize) {
+        match self {
+            StackOp::Dup => (1, 2),
+            StackOp::Swap => (2, 2),
+            StackOp::Drop => (1, 0),
+            StackOp::Over => (2, 3),
+            StackOp::Rot => (3, 3),
+        }
+    }
+
+    /// Returns the word that spells the stack operation in source text.
+    pub fn word(self) -> &'static str {
+        match self {
+            StackOp::Dup => "dup",
+            StackOp::Swap => "swap",
+            StackOp::Drop => "drop",
+            StackOp::Over => "over",
+            StackOp::Rot => "rot",
+        }
+    }
+
+    /// Returns the stack operation spelled `word`, if any.
+    pub fn from_word(word: &str) -> Option<StackOp> {
+        STACK_OPS.iter().cloned().find(|op| op.word() == word)
+    }
+
+    /// Rearranges the top of `stack`. Panics if `stack` holds fewer values
+    /// than `self.effect().0`.
+    pub fn apply<T: Clone>(self, stack: &mut Vec<T>) {
+        let n = stack.len();
+        assert!(n >= self.effect().0);
+        match self {
+            StackOp::Dup => {
+                let top = stack[n - 1].clone();
+                stack.push(top);
+            }
+            StackOp::Swap => stack.swap(n - 2, n - 1),
+            StackOp::Drop => {
+                stack.pop();
+            }
+            StackOp::Over => {
+                let second = stack[n - 2].clone();
+                stack.push(second);
+            }
+            StackOp::Rot => {
+                let third = stack.remove(n - 3);
+                stack.push(third);
+            }
+        }
+    }
+}
+
+/// One element of a postfix expression over numbers of type `N`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token<N = isize> {
     Operator(Operator),
-    Operand(isize),
+    Operand(N),
+    Stack(StackOp),
+    /// A named value, looked up in the `Environment` at evaluation time, or
+    /// a call to a word defined in an `Interpreter`.
+    Variable(String),
+    /// `:`, which starts the definition of a word in an `Interpreter`.
+    Define,
+    /// `;`, which ends the definition of a word.
+    End,
+}
+
+impl<N> Token<N> {
+    /// Converts the operand, if any, with `f` and keeps operators as they are.
+    pub fn map<M, F: FnOnce(&N) -> M>(&self, f: F) -> Token<M> {
+        match *self {
+            Token::Operator(op) => Token::Operator(op),
+            Token::Operand(ref value) => Token::Operand(f(value)),
+            Token::Stack(op) => Token::Stack(op),
+            Token::Variable(ref name) => Token::Variable(name.clone()),
+            Token::Define => Token::Define,
+            Token::End => Token::End,
+        }
+    }
+
+    /// Returns how many values the token pops off the stack and how many it
+    /// pushes in their place, or `None` for `Define` and `End`, which are not
+    /// evaluated.
+    pub fn stack_effect(&self) -> Option<(usize, usize)> {
+        match *self {
+            Token::Operand(_) | Token::Variable(_) => Some((0, 1)),
+            Token::Operator(op) => Some((op.arity(), 1)),
+            Token::Stack(op) => Some(op.effect()),
+            Token::Define | Token::End => None,
+        }
+    }
+}
+
+impl<N: fmt::Display> fmt::Display for Token<N> {
+    /// Writes the token the way `tokenize` reads it.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Operator(op) => write!(f, "{}", op.symbol()),
+            Token::Operand(ref value) => write!(f, "{}", value),
+            Token::Stack(op) => write!(f, "{}", op.word()),
+            Token::Variable(ref name) => write!(f, "{}", name),
+            Token::Define => write!(f, ":"),
+            Token::End => write!(f, ";"),
+        }
+    }
+}
+
+/// Describes why a postfix expression could not be evaluated.
+///
+/// Every variant except `Empty` carries the index of the offending `Token`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvalError {
+    /// The expression contains no tokens.
+    Empty,
+    /// The token at `index` needs more values than the `depth` values the
+    /// stack holds at that point.
+    StackUnderflow { index: usize, depth: usize },
+    /// `depth` values are left once every token is consumed, instead of one;
+    /// `index` is the token that produced the second value from the bottom.
+    LeftoverOperands { index: usize, depth: usize },
+    /// The operator at `index` divides by zero.
+    DivisionByZero { index: usize },
+    /// The operator at `index` produces a value that does not fit in the
+    /// numeric type.
+    Overflow { index: usize },
+    /// The variable at `index` has no value in the environment.
+    UnboundVariable { index: usize },
+    /// The token at `index` starts or ends a word definition that is malformed,
+    /// or appears outside an `Interpreter`.
+    Definition { index: usize },
+    /// The word called at `index` nests calls deeper than the interpreter
+    /// allows.
+    CallDepth { index: usize },
+    /// An operand of the operator at `index` is outside the operator's
+    /// domain, e.g. a negative exponent or shift amount.
+    Domain { index: usize },
+    /// The operator at `index` is not defined for the numeric type.
+    Unsupported { index: usize },
+    /// `derive` has no rule for the operator at `index`, e.g. `abs` or a
+    /// variable exponent.
+    NotDifferentiable { index: usize },
+    /// The token at `index` is beyond `Limits::max_tokens`.
+    TooManyTokens { index: usize },
+    /// The token at `index` would grow the stack beyond `Limits::max_depth`.
+    StackLimit { index: usize },
+    /// The token at `index` would take more than `Limits::max_steps` steps.
+    StepLimit { index: usize },
+    /// The divisor of the operator at `index`, `value`, has no inverse modulo
+    /// `modulus`.
+    NoInverse {
+        index: usize,
+        value: u64,
+        modulus: u64,
+    },
 }
 
+impl EvalError {
+    /// Returns the index of the offending token, if there is one.
+    pub fn index(&self) -> Option<usize> {
+        match *self {
+            EvalError::Empty => None,
+            EvalError::StackUnderflow { index, .. }
+            | EvalError::LeftoverOperands { index, .. }
+            | EvalError::DivisionByZero { index }
+            | EvalError::Overflow { index }
+            | EvalError::UnboundVariable { index }
+            | EvalError::Definition { index }
+            | EvalError::CallDepth { index }
+            | EvalError::Domain { index }
+            | EvalError::Unsupported { index }
+            | EvalError::NotDifferentiable { index }
+            | EvalError::TooManyTokens { index }
+            | EvalError::StackLimit { index }
+            | EvalError::StepLimit { index }
+            | EvalError::NoInverse { index, .. } => Some(index),
+        }
+    }
+
+    /// Returns the same error tied to the token at `index` instead, for
+    /// reporting a position relative to some other starting point.
+    pub fn with_index(self, index: usize) -> Self {
+        match self {
+            EvalError::Empty => EvalError::Empty,
+            EvalError::StackUnderflow { depth, .. } => EvalError::StackUnderflow { index, depth },
+            EvalError::LeftoverOperands { depth, .. } => {
+                EvalError::LeftoverOperands { index, depth }
+            }
+            EvalError::DivisionByZero { .. } => EvalError::DivisionByZero { index },
+            EvalError::Overflow { .. } => EvalError::Overflow { index },
+            EvalError::UnboundVariable { .. } => EvalError::UnboundVariable { index },
+            EvalError::Definition { .. } => EvalError::Definition { index },
+            EvalError::CallDepth { .. } => EvalError::CallDepth { index },
+            EvalError::Domain { .. } => EvalError::Domain { index },
+            EvalError::Unsupported { .. } => EvalError::Unsupported { index },
+            EvalError::NotDifferentiable { .. } => EvalError::NotDifferentiable { index },
+            EvalError::TooManyTokens { .. } => EvalError::TooManyTokens { index },
+            EvalError::StackLimit { .. } => EvalError::StackLimit { index },
+            EvalError::StepLimit { .. } => EvalError::StepLimit { index },
+            EvalError::NoInverse { value, modulus, .. } => EvalError::NoInverse {
+                index,
+                value,
+                modulus,
+            },
+        }
+    }
+
+    /// Ties an arithmetic failure to the token at `index`.
+    fn at(error: ArithError, index: usize) -> Self {
+        match error {
+            ArithError::DivisionByZero => EvalError::DivisionByZero { index },
+            ArithError::Overflow => EvalError::Overflow { index },
+            ArithError::Domain => EvalError::Domain { index },
+            ArithError::Unsupported => EvalError::Unsupported { index },
+            ArithError::NoInverse { value, modulus } => EvalError::NoInverse {
+                index,
+                value,
+                modulus,
+            },
+        }
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EvalError::Empty => write!(f, "empty expression"),
+            EvalError::StackUnderflow { index, depth } => write!(
+                f,
+                "token {}: not enough operands (stack depth {})",
+                index, depth
+            ),
+            EvalError::LeftoverOperands { index, depth } => write!(
+                f,
+                "token {}: operand is never used (stack depth {})",
+                index, depth
+            ),
+            EvalError::DivisionByZero { index } => write!(f, "token {}: division by zero", index),
+            EvalError::Overflow { index } => write!(f, "token {}: arithmetic overflow", index),
+            EvalError::UnboundVariable { index } => {
+                write!(f, "token {}: unbound variable", index)
+            }
+            EvalError::Definition { index } => {
+                write!(f, "token {}: misplaced word definition", index)
+            }
+            EvalError::CallDepth { index } => {
+                write!(f, "token {}: words nested too deeply", index)
+            }
+            EvalError::Domain { index } => write!(f, "token {}: operand out of domain", index),
+            EvalError::Unsupported { index } => {
+                write!(f, "token {}: operator not supported for this type", index)
+            }
+            EvalError::NotDifferentiable { index } => {
+                write!(f, "token {}: operator cannot be differentiated", index)
+            }
+            EvalError::TooManyTokens { index } => {
+                write!(f, "token {}: expression exceeds the token limit", index)
+            }
+            EvalError::StackLimit { index } => {
+                write!(f, "token {}: stack exceeds the depth limit", index)
+            }
+            EvalError::StepLimit { index } => {
+                write!(f, "token {}: step budget exhausted", index)
+            }
+            EvalError::NoInverse {
+                index,
+                value,
+                modulus,
+            } => write!(
+                f,
+                "token {}: {} has no inverse modulo {}",
+                index, value, modulus
+            ),
+        }
+    }
+}
+
+impl error::Error for EvalError {}
+
 /// Evaluates the postix expression.
 ///
 /// Input: a postfix expression, where each element contains an operator or operand.
-/// Returns: if the postfix expression is valid, returns `Some(value)`;
-///     otherwise, returns `None`.
-
-pub fn eval(tokens: &[Token]) -> Option<isize> {
-    // TODO
-    let length = (*tokens).len();
-    let mut digits = 0;
-    let mut operators = 0;
-    let mut current;
-    let mut new_array: Vec<isize> = Vec::new();
-    let mut count = 0;
-
-    for x in 0..length
-    {
-        match (*tokens)[x]
-        {
-            Token::Operand(_) => digits += 1,
-            Token::Operator(_) => operators += 1,
-        }
-    }
-    if operators != digits - 1
-    {
-        return None;
-    }
-
-    if length > 2
-    {
-        for x in 0..2
-        {
-            match (*tokens)[x]
-            {
-                Token::Operator(_) => return None,
-                _ => {},
+/// Returns: if the postfix expression is valid, returns `Ok(value)`;
+///     otherwise, returns `Err` describing the first problem found.
+pub fn eval<N: Number>(tokens: &[Token<N>]) -> Result<N, EvalError> {
+    eval_with_options(tokens, &EvalOptions::default())
+}
+
+/// Evaluates the postfix expression with the arithmetic semantics in `options`.
+pub fn eval_with_options<N: Number>(
+    tokens: &[Token<N>],
+    options: &EvalOptions,
+) -> Result<N, EvalError> {
+    run(tokens, &NoVariables, options)
+}
+
+/// Evaluates the postfix expression, looking up every `Token::Variable` in
+/// `env`.
+pub fn eval_with<N: Number, E: Environment<N> + ?Sized>(
+    tokens: &[Token<N>],
+    env: &E,
+) -> Result<N, EvalError> {
+    run(tokens, env, &EvalOptions::default())
+}
+
+fn run<N: Number, E: Environment<N> + ?Sized>(
+    tokens: &[Token<N>],
+    env: &E,
+    options: &EvalOptions,
+) -> Result<N, EvalError> {
+    validate(tokens, options)?;
+
+    let mut machine = Machine::new();
+    for (index, token) in tokens.iter().enumerate() {
+        machine.step(token, index, env, options)?;
+    }
+    machine.finish()
+}
+
+/// Rejects an expression that is too long for `options` or fails `analyze`,
+/// before any arithmetic runs.
+pub(crate) fn validate<N>(tokens: &[Token<N>], options: &EvalOptions) -> Result<(), EvalError> {
+    // Refuse an overlong expression before doing any work on it.
+    let max_tokens = options.limits.max_tokens;
+    if tokens.len() > max_tokens {
+        return Err(EvalError::TooManyTokens { index: max_tokens });
+    }
+    analyze(tokens).map(|_| ())
+}
+
+/// Proves from the stack effect of every token, without evaluating
+/// anything, that `tokens` never underflow the stack, contain no word
+/// definitions and leave exactly one value. Returns the largest number of
+/// values the stack ever holds.
+///
+/// `eval` and its variants, `trace`, `compile` and the functions that
+/// transform expressions run this first, so a malformed expression is
+/// rejected before any arithmetic runs. `Evaluator` and `Interpreter` do not:
+/// they receive their input piece by piece and check it as they go.
+pub fn analyze<N>(tokens: &[Token<N>]) -> Result<usize, EvalError> {
+    // Instead of values, the stack holds the index of the token that
+    // produced each value, so that leftover operands can be reported.
+    let mut origins: Vec<usize> = Vec::new();
+    let mut max_depth = 0;
+
+    for (index, token) in tokens.iter().enumerate() {
+        let depth = origins.len();
+        let needed = match token.stack_effect() {
+            Some((pops, _)) => pops,
+            None => return Err(EvalError::Definition { index }),
+        };
+        if depth < needed {
+            return Err(EvalError::StackUnderflow { index, depth });
+        }
+        match *token {
+            Token::Stack(op) => op.apply(&mut origins),
+            _ => {
+                origins.truncate(depth - needed);
+                origins.push(index);
             }
         }
+        max_depth = cmp::max(max_depth, origins.len());
     }
 
-    for x in 0..length
-    {
-        match (*tokens)[x]
-        {
-            Token::Operand(a) =>
-            {
-                new_array.push(a);
-                count += 1;
-            },
-            Token::Operator(Operator::Add) =>
-            {
-                current = new_array[count-2] + new_array[count-1];
-                new_array.pop();
-                new_array.pop();
-                new_array.push(current);
-                count -= 1;
-            },
-            Token::Operator(Operator::Sub) =>
-            {
-                current = new_array[count-2] - new_array[count-1];
-                new_array.pop();
-                new_array.pop();
-                new_array.push(current);
-                count -= 1;
-            },
-            Token::Operator(Operator::Mul) =>
-            {
-                current = new_array[count-2] * new_array[count-1];
-                new_array.pop();
-                new_array.pop();
-                new_array.push(current);
-                count -= 1;
-            },
-            Token::Operator(Operator::Div) =>
-            {
-                current = new_array[count-2] / new_array[count-1];
-                new_array.pop();
-                new_array.pop();
-                new_array.push(current);
-                count -= 1;
-            },
+    match origins.len() {
+        0 => Err(EvalError::Empty),
+        1 => Ok(max_depth),
+        depth => Err(EvalError::LeftoverOperands {
+            index: origins[1],
+            depth,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval() {
+        let tokens = [
+            Token::Operand(3),
+            Token::Operand(4),
+            Token::Operator(Operator::Add),
+            Token::Operand(2),
+            Token::Operator(Operator::Mul),
+        ];
+        assert_eq!(eval(&tokens), Ok(14));
+        assert_eq!(eval(&[Token::Operand(-5)]), Ok(-5));
+    }
+
+    #[test]
+    fn test_analyze() {
+        assert_eq!(analyze(&tokenize("1 2 3 + +").unwrap()), Ok(3));
+        assert_eq!(analyze(&tokenize("3 dup dup * * 4 +").unwrap()), Ok(3));
+        // Balanced counts do not make a valid expression.
+        let tokens = tokenize("1 + 2 3").unwrap();
+        assert_eq!(
+            analyze(&tokens),
+            Err(EvalError::StackUnderflow { index: 1, depth: 1 })
+        );
+        assert_eq!(
+            eval(&tokens),
+            Err(EvalError::StackUnderflow { index: 1, depth: 1 })
+        );
+        assert_eq!(
+            analyze(&tokenize("1 2 drop 3").unwrap()),
+            Err(EvalError::LeftoverOperands { index: 3, depth: 2 })
+        );
+    }
+
+    #[test]
+    fn test_eval_errors() {
+        assert_eq!(eval::<isize>(&[]), Err(EvalError::Empty));
+        assert_eq!(
+            eval(&[Token::Operand(1), Token::Operator(Operator::Add)]),
+            Err(EvalError::StackUnderflow { index: 1, depth: 1 })
+        );
+        assert_eq!(
+            eval(&[
+                Token::Operand(1),
+                Token::Operand(2),
+                Token::Operand(3),
+                Token::Operator(Operator::Add)
+            ]),
+            Err(EvalError::LeftoverOperands { index: 3, depth: 2 })
+        );
+        assert_eq!(
+            eval(&[
+                Token::Operand(1),
+                Token::Operand(0),
+                Token::Operator(Operator::Div)
+            ]),
+            Err(EvalError::DivisionByZero { index: 2 })
+        );
+        assert_eq!(
+            eval(&[
+                Token::Operand(isize::MAX),
+                Token::Operand(1),
+                Token::Operator(Operator::Add)
+            ]),
+            Err(EvalError::Overflow { index: 2 })
+        );
+        assert_eq!(
+            eval(&[
+                Token::Operand(isize::MIN),
+                Token::Operand(-1),
+                Token::Operator(Operator::Div)
+            ]),
+            Err(EvalError::Overflow { index: 2 })
+        );
+    }
+
+    #[test]
+    fn test_eval_with_options() {
+        let add = [
+            Token::Operand(isize::MAX),
+            Token::Operand(1),
+            Token::Operator(Operator::Add),
+        ];
+        let div = [
+            Token::Operand(-7),
+            Token::Operand(2),
+            Token::Operator(Operator::Div),
+        ];
+        let wrapping = EvalOptions::new(Overflow::Wrapping, Division::Truncating);
+        let saturating = EvalOptions::new(Overflow::Saturating, Division::Euclidean);
+
+        assert_eq!(eval_with_options(&add, &wrapping), Ok(isize::MIN));
+        assert_eq!(eval_with_options(&add, &saturating), Ok(isize::MAX));
+        assert_eq!(eval_with_options(&div, &wrapping), Ok(-3));
+        assert_eq!(eval_with_options(&div, &saturating), Ok(-4));
+    }
+
+    #[test]
+    fn test_extended_operators() {
+        let tokens = tokenize("2 10 ** 7 % neg 3 abs max 6 5 ^ <<").unwrap();
+        assert_eq!(eval(&tokens), Ok(24));
+        assert_eq!(eval(&tokenize("1 neg neg neg").unwrap()), Ok(-1));
+        assert_eq!(
+            eval(&tokenize("neg").unwrap()),
+            Err(EvalError::StackUnderflow { index: 0, depth: 0 })
+        );
+        assert_eq!(
+            eval(&tokenize("2 -1 **").unwrap()),
+            Err(EvalError::Domain { index: 2 })
+        );
+        let floats = [
+            Token::Operand(1.5),
+            Token::Operand(1.0),
+            Token::Operator(Operator::Shl),
+        ];
+        assert_eq!(eval(&floats), Err(EvalError::Unsupported { index: 2 }));
+        for op in OPERATORS.iter() {
+            assert_eq!(Operator::from_symbol(op.symbol()), Some(*op));
         }
     }
 
+    #[test]
+    fn test_conditional_operators() {
+        assert_eq!(
+            eval(&tokenize("3 4 < 4 4 <= and 2 1 == or").unwrap()),
+            Ok(1)
+        );
+        assert_eq!(eval(&tokenize("5 0 != not 7 -7 >= and").unwrap()), Ok(0));
+        // Ten percent off orders of more than 100.
+        let price = tokenize("qty 100 > qty 9 * qty 10 * ?:").unwrap();
+        assert_eq!(eval_with(&price, &[("qty", 150)][..]), Ok(1350));
+        assert_eq!(eval_with(&price, &[("qty", 100)][..]), Ok(1000));
+        let nan = [
+            Token::Operand(f64::NAN),
+            Token::Operand(1.0),
+            Token::Operator(Operator::Lt),
+        ];
+        assert_eq!(eval(&nan), Err(EvalError::Domain { index: 2 }));
+    }
+
+    #[test]
+    fn test_stack_words() {
+        assert_eq!(eval(&tokenize("7 dup *").unwrap()), Ok(49));
+        assert_eq!(eval(&tokenize("1 2 swap -").unwrap()), Ok(1));
+        assert_eq!(eval(&tokenize("1 2 drop").unwrap()), Ok(1));
+        assert_eq!(eval(&tokenize("5 3 over - *").unwrap()), Ok(-10));
+        assert_eq!(eval(&tokenize("1 2 3 rot - *").unwrap()), Ok(4));
+        assert_eq!(
+            eval(&tokenize("1 drop drop").unwrap()),
+            Err(EvalError::StackUnderflow { index: 2, depth: 0 })
+        );
+        assert_eq!(
+            eval(&tokenize("1 0 / 2 dup").unwrap()),
+            Err(EvalError::LeftoverOperands { index: 3, depth: 3 })
+        );
+    }
+
+    #[test]
+    fn test_eval_with() {
+        use std::collections::HashMap;
 
-    Some(new_array[0])
+        let formula = tokenize("price qty * 5 -").unwrap();
+        let mut record: HashMap<String, isize> = HashMap::new();
+        record.insert("price".to_string(), 12);
+        record.insert("qty".to_string(), 3);
+        assert_eq!(eval_with(&formula, &record), Ok(31));
+        assert_eq!(eval_with(&formula, &[("price", 2), ("qty", 4)][..]), Ok(3));
+
+        record.remove("qty");
+        assert_eq!(
+            eval_with(&formula, &record),
+            Err(EvalError::UnboundVariable { index: 1 })
+        );
+        assert_eq!(eval(&formula), Err(EvalError::UnboundVariable { index: 0 }));
+    }
+
+    #[test]
+    fn test_limits() {
+        let limited = |limits: Limits| EvalOptions::default().with_limits(limits);
+        let tokens = tokenize("1 2 3 + + 4 +").unwrap();
+        let errors = [
+            (
+                Limits {
+                    max_tokens: 5,
+                    ..Limits::default()
+                },
+                EvalError::TooManyTokens { index: 5 },
+            ),
+            (
+                Limits {
+                    max_depth: 2,
+                    ..Limits::default()
+                },
+                EvalError::StackLimit { index: 2 },
+            ),
+            (
+                Limits {
+                    max_steps: 4,
+                    ..Limits::default()
+                },
+                EvalError::StepLimit { index: 4 },
+            ),
+        ];
+        for &(limits, error) in &errors {
+            assert_eq!(eval_with_options(&tokens, &limited(limits)), Err(error));
+        }
+
+        // Calls of words count as steps too.
+        let mut interpreter = Interpreter::with_options(limited(Limits {
+            max_steps: 6,
+            ..Limits::default()
+        }));
+        assert_eq!(
+            interpreter.eval(&tokenize(": f 1 + ; 0 f f").unwrap()),
+            Err(EvalError::StepLimit { index: 7 })
+        );
+
+        // Definitions count against both the input and the dictionary.
+        let mut interpreter: Interpreter = Interpreter::with_options(limited(Limits {
+            max_tokens: 12,
+            max_word_tokens: 4,
+            ..Limits::default()
+        }));
+        assert_eq!(
+            interpreter.eval(&tokenize(": a 1 2 3 4 5 ; : b 1 ; 1 2").unwrap()),
+            Err(EvalError::TooManyTokens { index: 12 })
+        );
+        assert!(!interpreter.is_defined("a"));
+        assert_eq!(
+            interpreter.eval(&tokenize(": a 1 2 + ; : b 1 2 ;").unwrap()),
+            Err(EvalError::TooManyTokens { index: 9 })
+        );
+        assert!(interpreter.is_defined("a") && !interpreter.is_defined("b"));
+        assert_eq!(
+            interpreter.eval(&tokenize(": a 1 ; : b 2 3 ;").unwrap()),
+            Ok(None)
+        );
+    }
 }
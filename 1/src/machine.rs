@@ -0,0 +1,119 @@
+use super::{Environment, EvalError, EvalOptions, Number, Token};
+
+/// The evaluation stack, checked one token at a time.
+///
+/// Next to every value it keeps the index of the token that produced it, so
+/// that leftover operands can be reported, and it counts the steps taken
+/// against `Limits::max_steps`.
+#[derive(Clone, Debug)]
+pub(crate) struct Machine<N> {
+    values: Vec<N>,
+    origins: Vec<usize>,
+    steps: usize,
+}
+
+impl<N: Number> Machine<N> {
+    pub(crate) fn new() -> Self {
+        Machine {
+            values: Vec::new(),
+            origins: Vec::new(),
+            steps: 0,
+        }
+    }
+
+    /// Takes one step out of the budget in `options`, on behalf of the token
+    /// at `index`.
+    pub(crate) fn charge_step(
+        &mut self,
+        index: usize,
+        options: &EvalOptions,
+    ) -> Result<(), EvalError> {
+        if self.steps >= options.limits.max_steps {
+            return Err(EvalError::StepLimit { index });
+        }
+        self.steps += 1;
+        Ok(())
+    }
+
+    /// Executes `token`, found at `index`, on the stack.
+    pub(crate) fn step<E: Environment<N> + ?Sized>(
+        &mut self,
+        token: &Token<N>,
+        index: usize,
+        env: &E,
+        options: &EvalOptions,
+    ) -> Result<(), EvalError> {
+        let limits = &options.limits;
+        if index >= limits.max_tokens {
+            return Err(EvalError::TooManyTokens { index });
+        }
+        self.charge_step(index, options)?;
+        let depth = self.values.len();
+        if let Some((pops, pushes)) = token.stack_effect() {
+            if depth >= pops && depth - pops + pushes > limits.max_depth {
+                return Err(EvalError::StackLimit { index });
+            }
+        }
+        match *token {
+            Token::Operand(ref value) => self.push(value.clone(), index),
+            Token::Variable(ref name) => match env.get(name) {
+                Some(value) => self.push(value, index),
+                None => return Err(EvalError::UnboundVariable { index }),
+            },
+            Token::Operator(op) => {
+                if depth < op.arity() {
+                    return Err(EvalError::StackUnderflow { index, depth });
+                }
+                let at = depth - op.arity();
+                let result = op
+                    .apply(&self.values[at..], options)
+                    .map_err(|e| EvalError::at(e, index))?;
+                self.values.truncate(at);
+                self.origins.truncate(at);
+                self.push(result, index);
+            }
+            Token::Stack(op) => {
+                if depth < op.effect().0 {
+                    return Err(EvalError::StackUnderflow { index, depth });
+                }
+                op.apply(&mut self.values);
+                op.apply(&mut self.origins);
+            }
+            Token::Define | Token::End => return Err(EvalError::Definition { index }),
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, value: N, index: usize) {
+        self.values.push(value);
+        self.origins.push(index);
+    }
+
+    /// Returns the values on the stack, bottom first.
+    pub(crate) fn values(&self) -> &[N] {
+        &self.values
+    }
+
+    /// Returns the values on the stack, bottom first, for changing in place.
+    pub(crate) fn values_mut(&mut self) -> &mut [N] {
+        &mut self.values
+    }
+
+    /// Checks that exactly one value is left on the stack.
+    pub(crate) fn check_done(&self) -> Result<(), EvalError> {
+        match self.values.len() {
+            0 => Err(EvalError::Empty),
+            1 => Ok(()),
+            depth => Err(EvalError::LeftoverOperands {
+                index: self.origins[1],
+                depth,
+            }),
+        }
+    }
+
+    /// Returns the single value left on the stack.
+    pub(crate) fn finish(mut self) -> Result<N, EvalError> {
+        self.check_done()?;
+        Ok(self.values.pop().unwrap())
+    }
+}
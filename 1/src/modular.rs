@@ -0,0 +1,321 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+use super::machine::Machine;
+use super::{validate, ArithError, EvalError, EvalOptions, NoVariables, Number, Token};
+
+/// An integer modulo `modulus`, for evaluation in modular arithmetic.
+///
+/// Division multiplies by the modular inverse. It fails with
+/// `ArithError::DivisionByZero` if the divisor is 0 modulo `modulus`, and
+/// with `ArithError::NoInverse` if it has no inverse otherwise. Next to the
+/// residue, the value remembers the plain integer it stands for as long as that is known,
+/// so that `2 10 **` raises 2 to the 10th power even modulo 7. An exponent
+/// that is only known as a residue, e.g. after a division, is out of the
+/// domain. Comparisons order the residues.
+///
+/// `Number::zero` and `Number::one`, and so the results of comparisons, are
+/// not tied to a modulus yet, and take that of the first value they are
+/// combined with; `modulus` returns 0 for them. Dividing two such values is
+/// out of the domain unless the quotient is an integer. `eval_mod` ties every
+/// result to its modulus, so there this never happens.
+#[derive(Clone, Copy, Debug)]
+pub struct Modular {
+    residue: u64,
+    modulus: u64,
+    exact: Option<i128>,
+}
+
+impl Modular {
+    /// Creates `value` modulo `modulus`, which must not be 0.
+    pub fn new(value: i128, modulus: u64) -> Result<Modular, ArithError> {
+        if modulus == 0 {
+            return Err(ArithError::Domain);
+        }
+        Ok(Modular {
+            residue: value.rem_euclid(modulus as i128) as u64,
+            modulus,
+            exact: Some(value),
+        })
+    }
+
+    /// Returns the value in `0..modulus`.
+    pub fn residue(&self) -> u64 {
+        self.residue
+    }
+
+    /// Returns the modulus, or 0 for a value not tied to one yet.
+    pub fn modulus(&self) -> u64 {
+        self.modulus
+    }
+
+    fn unbound(value: i128) -> Modular {
+        Modular {
+            residue: value as u64,
+            modulus: 0,
+            exact: Some(value),
+        }
+    }
+
+    /// Ties a value without a modulus to `modulus`.
+    fn bind(&mut self, modulus: u64) {
+        if self.modulus == 0 {
+            self.residue = self.residue_mod(modulus);
+            self.modulus = modulus;
+        }
+    }
+
+    /// Returns the residue modulo `modulus`, which is either `self.modulus`
+    /// or the modulus an unbound value takes on.
+    fn residue_mod(&self, modulus: u64) -> u64 {
+        if self.modulus == modulus {
+            return self.residue;
+        }
+        // Only unbound values, which are always exact, change modulus.
+        self.exact.unwrap().rem_euclid(modulus as i128) as u64
+    }
+
+    /// Returns the modulus shared by `self` and `rhs`; 0 if neither has one.
+    fn common_modulus(&self, rhs: &Modular) -> Result<u64, ArithError> {
+        match (self.modulus, rhs.modulus) {
+            (0, m) | (m, 0) => Ok(m),
+            (m, n) if m == n => Ok(m),
+            _ => Err(ArithError::Domain),
+        }
+    }
+
+    /// Combines two values, computing the exact integer with `exact` and the
+    /// residue with `residue`. The error from `exact` only counts if neither
+    /// value has a modulus; otherwise the exact integer is just forgotten.
+    fn binary<F, G>(&self, rhs: &Modular, exact: F, residue: G) -> Result<Modular, ArithError>
+    where
+        F: Fn(i128, i128) -> Result<i128, ArithError>,
+        G: Fn(u128, u128, u128) -> Result<u128, ArithError>,
+    {
+        let modulus = self.common_modulus(rhs)?;
+        if modulus == 0 {
+            // Unbound values are always exact.
+            return exact(self.exact.unwrap(), rhs.exact.unwrap()).map(Modular::unbound);
+        }
+        let exact = match (self.exact, rhs.exact) {
+            (Some(a), Some(b)) => exact(a, b).ok(),
+            _ => None,
+        };
+        let (a, b) = (self.residue_mod(modulus), rhs.residue_mod(modulus));
+        let residue = residue(a as u128, b as u128, modulus as u128)?;
+        Ok(Modular {
+            residue: residue as u64,
+            modulus,
+            exact,
+        })
+    }
+}
+
+/// Two values are equal if their residues and moduli are.
+impl PartialEq for Modular {
+    fn eq(&self, other: &Modular) -> bool {
+        (self.residue, self.modulus) == (other.residue, other.modulus)
+    }
+}
+
+impl Eq for Modular {}
+
+/// Returns the inverse of `a` modulo `m`, if `a` and `m` are coprime.
+fn inverse(a: u128, m: u128) -> Option<u128> {
+    // Extended Euclid, tracking only the coefficient of `a`.
+    let (mut r0, mut r1) = (m as i128, a as i128);
+    let (mut t0, mut t1) = (0i128, 1i128);
+    while r1 != 0 {
+        let q = r0 / r1;
+        let (r, t) = (r0 - q * r1, t0 - q * t1);
+        r0 = r1;
+        r1 = r;
+        t0 = t1;
+        t1 = t;
+    }
+    if r0 != 1 {
+        return None;
+    }
+    Some(t0.rem_euclid(m as i128) as u128)
+}
+
+/// Modular arithmetic never overflows or rounds, so `options` are ignored.
+/// `abs`, `min`, `max`, `%` and the bitwise operators are unsupported.
+impl Number for Modular {
+    fn zero() -> Self {
+        Modular::unbound(0)
+    }
+
+    fn one() -> Self {
+        Modular::unbound(1)
+    }
+
+    fn add(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        let exact = |a: i128, b| a.checked_add(b).ok_or(ArithError::Overflow);
+        self.binary(rhs, exact, |a, b, m| Ok((a + b) % m))
+    }
+
+    fn sub(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        let exact = |a: i128, b| a.checked_sub(b).ok_or(ArithError::Overflow);
+        self.binary(rhs, exact, |a, b, m| Ok((a + m - b) % m))
+    }
+
+    fn mul(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        let exact = |a: i128, b| a.checked_mul(b).ok_or(ArithError::Overflow);
+        self.binary(rhs, exact, |a, b, m| Ok(a * b % m))
+    }
+
+    fn div(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        let modulus = self.common_modulus(rhs)?;
+        if rhs.residue_mod(modulus) == 0 {
+            return Err(ArithError::DivisionByZero);
+        }
+        // Without a modulus, only integers that divide evenly have a quotient.
+        let exact = |a: i128, b: i128| match a.checked_rem(b) {
+            Some(0) => a.checked_div(b).ok_or(ArithError::Overflow),
+            _ => Err(ArithError::Domain),
+        };
+        self.binary(rhs, exact, |a, b, m| match inverse(b, m) {
+            Some(b_inv) => Ok(a * b_inv % m),
+            None => Err(ArithError::NoInverse {
+                value: b as u64,
+                modulus: m as u64,
+            }),
+        })
+    }
+
+    /// A negative exponent raises the inverse of the base.
+    fn pow(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+        let exponent = rhs.exact.ok_or(ArithError::Domain)?;
+        if exponent < 0 {
+            let inverse = Modular::one().div(self, options)?;
+            let positive = Modular::unbound(exponent.checked_neg().ok_or(ArithError::Overflow)?);
+            return inverse.pow(&positive, options);
+        }
+        let exact = |a: i128, _| {
+            u32::try_from(exponent)
+                .ok()
+                .and_then(|e| a.checked_pow(e))
+                .ok_or(ArithError::Overflow)
+        };
+        self.binary(rhs, exact, |a, _, m| {
+            // Square and multiply.
+            let (mut base, mut e, mut result) = (a, exponent as u128, 1 % m);
+            while e > 0 {
+                if e & 1 == 1 {
+                    result = result * base % m;
+                }
+                base = base * base % m;
+                e >>= 1;
+            }
+            Ok(result)
+        })
+    }
+
+    fn neg(&self, options: &EvalOptions) -> Result<Self, ArithError> {
+        Modular::zero().sub(self, options)
+    }
+
+    fn compare(&self, rhs: &Self) -> Result<Ordering, ArithError> {
+        match self.common_modulus(rhs)? {
+            0 => Ok(self.exact.cmp(&rhs.exact)),
+            m => Ok(self.residue_mod(m).cmp(&rhs.residue_mod(m))),
+        }
+    }
+}
+
+/// Evaluates the postfix expression modulo `modulus` and returns the residue
+/// of the result. Panics if `modulus` is 0.
+///
+/// Division by a multiple of `modulus` fails with `EvalError::DivisionByZero`,
+/// and by any other value without an inverse modulo `modulus` with
+/// `EvalError::NoInverse`, which names the divisor.
+pub fn eval_mod(tokens: &[Token], modulus: u64) -> Result<u64, EvalError> {
+    assert!(modulus > 0, "modulus must not be 0");
+    let tokens: Vec<Token<Modular>> = tokens
+        .iter()
+        .map(|t| t.map(|&n| Modular::new(n as i128, modulus).unwrap()))
+        .collect();
+    let options = EvalOptions::default();
+    validate(&tokens, &options)?;
+
+    let mut machine = Machine::new();
+    for (index, token) in tokens.iter().enumerate() {
+        machine.step(token, index, &NoVariables, &options)?;
+        // Only an operator can leave a value without a modulus, on top.
+        if let Some(top) = machine.values_mut().last_mut() {
+            top.bind(modulus);
+        }
+    }
+    Ok(machine.finish()?.residue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{eval, tokenize};
+    use super::*;
+
+    fn eval_source(source: &str, modulus: u64) -> Result<u64, EvalError> {
+        eval_mod(&tokenize(source).unwrap(), modulus)
+    }
+
+    #[test]
+    fn test_eval_mod() {
+        assert_eq!(eval_source("5 9 -", 7), Ok(3));
+        assert_eq!(eval_source("3 4 /", 7), Ok(6));
+        assert_eq!(eval_source("2 10 **", 7), Ok(2));
+        assert_eq!(eval_source("2 6 1 - **", 7), Ok(4));
+        assert_eq!(eval_source("3 -1 **", 7), Ok(5));
+        assert_eq!(
+            eval_source("123456789 65537 **", 1_000_000_007),
+            Ok(560583526)
+        );
+        assert_eq!(eval_source("10 3 > 4 ==", 5), Ok(0));
+        // 3 / 2 modulo 7, from comparison results.
+        let three_halves = "1 2 < 1 2 < 1 2 < + + 1 2 < 1 2 < + /";
+        assert_eq!(eval_source(three_halves, 7), Ok(5));
+    }
+
+    #[test]
+    fn test_eval_mod_errors() {
+        assert_eq!(
+            eval_source("1 2 4 / +", 8),
+            Err(EvalError::NoInverse {
+                index: 3,
+                value: 4,
+                modulus: 8
+            })
+        );
+        assert_eq!(
+            eval_source("2 3 4 / **", 7),
+            Err(EvalError::Domain { index: 4 })
+        );
+        assert_eq!(
+            eval_source("5 14 /", 7),
+            Err(EvalError::DivisionByZero { index: 2 })
+        );
+        assert_eq!(
+            eval_source("5 0 /", 1),
+            Err(EvalError::DivisionByZero { index: 2 })
+        );
+        assert_eq!(
+            eval_source("1 2 < 1 2 > /", 7),
+            Err(EvalError::DivisionByZero { index: 6 })
+        );
+
+        // Outside `eval_mod`, comparison results have no modulus.
+        let truth = |source| {
+            let tokens = tokenize(source).unwrap();
+            let tokens: Vec<Token<Modular>> = tokens
+                .iter()
+                .map(|t| t.map(|&n| Modular::new(n as i128, 7).unwrap()))
+                .collect();
+            eval(&tokens)
+        };
+        assert_eq!(
+            truth("1 2 < dup + 1 2 < swap /"),
+            Err(EvalError::Domain { index: 9 })
+        );
+        assert_eq!(truth("1 2 < dup + dup /").map(|n| n.modulus()), Ok(0));
+    }
+}
@@ -0,0 +1,564 @@
+use std::cmp;
+use std::convert::TryFrom;
+
+use super::{Division, EvalOptions, Overflow};
+
+/// Failure of a single arithmetic operation, before it is tied to a token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArithError {
+    DivisionByZero,
+    Overflow,
+    /// An operand is outside the operation's domain.
+    Domain,
+    /// The operation is not defined for the type.
+    Unsupported,
+    /// The divisor `value` has no inverse modulo `modulus`.
+    NoInverse {
+        value: u64,
+        modulus: u64,
+    },
+}
+
+/// A value the postfix evaluator can compute with.
+///
+/// Implemented for every primitive integer and float type. Implement it for
+/// your own type (e.g. an arbitrary-precision integer) to evaluate
+/// `Token<YourType>` expressions. Each operation decides for itself how much
+/// of `options` applies to it. Only the identities and the four basic
+/// operations are required; the others report `ArithError::Unsupported`
+/// unless overridden.
+pub trait Number: Clone {
+    /// Returns 0, the identity of `add`.
+    fn zero() -> Self;
+    /// Returns 1, the identity of `mul`.
+    fn one() -> Self;
+
+    /// Returns `self + rhs`.
+    fn add(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError>;
+    /// Returns `self - rhs`.
+    fn sub(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError>;
+    /// Returns `self * rhs`.
+    fn mul(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError>;
+    /// Returns `self / rhs`.
+    fn div(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError>;
+
+    /// Returns how `self` is ordered relative to `rhs`. Used by the
+    /// comparison and logical operators, which treat anything other than
+    /// zero as true.
+    fn compare(&self, _rhs: &Self) -> Result<cmp::Ordering, ArithError> {
+        Err(ArithError::Unsupported)
+    }
+
+    /// Returns `true` if the type has a negative zero that arithmetic tells
+    /// apart from 0, as floats do. `optimize` then leaves `x 0 +` alone,
+    /// since it is +0 for x = -0.
+    fn signed_zero() -> bool {
+        false
+    }
+
+    /// Returns the remainder of `self / rhs`, rounded like `div`.
+    fn rem(&self, _rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Err(ArithError::Unsupported)
+    }
+    /// Returns `self` raised to the power `rhs`.
+    fn pow(&self, _rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Err(ArithError::Unsupported)
+    }
+    /// Returns `-self`.
+    fn neg(&self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Err(ArithError::Unsupported)
+    }
+    /// Returns the absolute value of `self`.
+    fn abs(&self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Err(ArithError::Unsupported)
+    }
+    /// Returns the smaller of `self` and `rhs`.
+    fn min(&self, _rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Err(ArithError::Unsupported)
+    }
+    /// Returns the larger of `self` and `rhs`.
+    fn max(&self, _rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Err(ArithError::Unsupported)
+    }
+    /// Returns the bitwise and of `self` and `rhs`.
+    fn bitand(&self, _rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Err(ArithError::Unsupported)
+    }
+    /// Returns the bitwise or of `self` and `rhs`.
+    fn bitor(&self, _rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Err(ArithError::Unsupported)
+    }
+    /// Returns the bitwise exclusive or of `self` and `rhs`.
+    fn bitxor(&self, _rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Err(ArithError::Unsupported)
+    }
+    /// Returns `self` shifted left by `rhs` bits.
+    fn shl(&self, _rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Err(ArithError::Unsupported)
+    }
+    /// Returns `self` shifted right by `rhs` bits.
+    fn shr(&self, _rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Err(ArithError::Unsupported)
+    }
+}
+
+/// Returns `true` if `1 / 2 * 2 == 1` for `N`, i.e. division does not
+/// truncate.
+pub(crate) fn divides_exactly<N: Number>() -> bool {
+    let options = EvalOptions::default();
+    let two = match N::one().add(&N::one(), &options) {
+        Ok(two) => two,
+        Err(_) => return false,
+    };
+    let product = N::one()
+        .div(&two, &options)
+        .and_then(|half| half.mul(&two, &options));
+    match product {
+        Ok(product) => product.compare(&N::one()) == Ok(cmp::Ordering::Equal),
+        Err(_) => false,
+    }
+}
+
+/// Converts an exponent or shift amount to `u128`. Negative values are out of
+/// the domain.
+fn exponent<T>(n: T) -> Result<u128, ArithError>
+where
+    u128: TryFrom<T>,
+{
+    u128::try_from(n).map_err(|_| ArithError::Domain)
+}
+
+/// Narrows an exponent or shift amount to `u32`. Values beyond `u32::MAX`
+/// become 128 or 129, whichever has the same parity: that keeps `x ** n` for
+/// 0 and ±1, and overflows any other base or shift as the original does.
+fn small_exponent(n: u128) -> u32 {
+    u32::try_from(n).unwrap_or(128 + (n % 2) as u32)
+}
+
+macro_rules! impl_integer {
+    (signed $($t:ident)*) => {$(
+        impl_integer!(@impl $t, |x: $t, options: &EvalOptions| match options.overflow {
+            Overflow::Checked => x.checked_abs().ok_or(ArithError::Overflow),
+            Overflow::Wrapping => Ok(x.wrapping_abs()),
+            Overflow::Saturating => Ok(x.saturating_abs()),
+        });
+    )*};
+    (unsigned $($t:ident)*) => {$(
+        impl_integer!(@impl $t, |x: $t, _: &EvalOptions| Ok(x));
+    )*};
+    (@impl $t:ident, $abs:expr) => {
+        impl Number for $t {
+            fn zero() -> Self {
+                0
+            }
+
+            fn one() -> Self {
+                1
+            }
+
+            fn add(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+                match options.overflow {
+                    Overflow::Checked => self.checked_add(*rhs).ok_or(ArithError::Overflow),
+                    Overflow::Wrapping => Ok(self.wrapping_add(*rhs)),
+                    Overflow::Saturating => Ok(self.saturating_add(*rhs)),
+                }
+            }
+
+            fn sub(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+                match options.overflow {
+                    Overflow::Checked => self.checked_sub(*rhs).ok_or(ArithError::Overflow),
+                    Overflow::Wrapping => Ok(self.wrapping_sub(*rhs)),
+                    Overflow::Saturating => Ok(self.saturating_sub(*rhs)),
+                }
+            }
+
+            fn mul(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+                match options.overflow {
+                    Overflow::Checked => self.checked_mul(*rhs).ok_or(ArithError::Overflow),
+                    Overflow::Wrapping => Ok(self.wrapping_mul(*rhs)),
+                    Overflow::Saturating => Ok(self.saturating_mul(*rhs)),
+                }
+            }
+
+            fn div(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+                if *rhs == 0 {
+                    return Err(ArithError::DivisionByZero);
+                }
+                let euclid = options.division == Division::Euclidean;
+                match options.overflow {
+                    Overflow::Checked if euclid => {
+                        self.checked_div_euclid(*rhs).ok_or(ArithError::Overflow)
+                    }
+                    Overflow::Checked => self.checked_div(*rhs).ok_or(ArithError::Overflow),
+                    Overflow::Wrapping if euclid => Ok(self.wrapping_div_euclid(*rhs)),
+                    Overflow::Wrapping => Ok(self.wrapping_div(*rhs)),
+                    // The only overflowing quotient is `MIN / -1`, which saturates to `MAX`.
+                    Overflow::Saturating if euclid => {
+                        Ok(self.checked_div_euclid(*rhs).unwrap_or($t::MAX))
+                    }
+                    Overflow::Saturating => Ok(self.saturating_div(*rhs)),
+                }
+            }
+
+            fn rem(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+                if *rhs == 0 {
+                    return Err(ArithError::DivisionByZero);
+                }
+                // `MIN % -1` is 0, but overflows as a checked operation.
+                let euclid = options.division == Division::Euclidean;
+                match options.overflow {
+                    Overflow::Checked if euclid => {
+                        self.checked_rem_euclid(*rhs).ok_or(ArithError::Overflow)
+                    }
+                    Overflow::Checked => self.checked_rem(*rhs).ok_or(ArithError::Overflow),
+                    _ if euclid => Ok(self.wrapping_rem_euclid(*rhs)),
+                    _ => Ok(self.wrapping_rem(*rhs)),
+                }
+            }
+
+            fn pow(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+                let exp = exponent(*rhs)?;
+                match options.overflow {
+                    Overflow::Checked => {
+                        self.checked_pow(small_exponent(exp)).ok_or(ArithError::Overflow)
+                    }
+                    Overflow::Wrapping if exp <= u32::MAX as u128 => {
+                        Ok(self.wrapping_pow(exp as u32))
+                    }
+                    Overflow::Wrapping => {
+                        // Square and multiply, as `wrapping_pow` takes a `u32`.
+                        let (mut base, mut e, mut result) = (*self, exp, 1 as $t);
+                        while e > 0 {
+                            if e & 1 == 1 {
+                                result = result.wrapping_mul(base);
+                            }
+                            base = base.wrapping_mul(base);
+                            e >>= 1;
+                        }
+                        Ok(result)
+                    }
+                    Overflow::Saturating => Ok(self.saturating_pow(small_exponent(exp))),
+                }
+            }
+
+            fn neg(&self, options: &EvalOptions) -> Result<Self, ArithError> {
+                (0 as $t).sub(self, options)
+            }
+
+            fn abs(&self, options: &EvalOptions) -> Result<Self, ArithError> {
+                ($abs)(*self, options)
+            }
+
+            fn min(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+                Ok(cmp::min(*self, *rhs))
+            }
+
+            fn max(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+                Ok(cmp::max(*self, *rhs))
+            }
+
+            fn compare(&self, rhs: &Self) -> Result<cmp::Ordering, ArithError> {
+                Ok(self.cmp(rhs))
+            }
+
+            fn bitand(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+                Ok(*self & *rhs)
+            }
+
+            fn bitor(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+                Ok(*self | *rhs)
+            }
+
+            fn bitxor(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+                Ok(*self ^ *rhs)
+            }
+
+            fn shl(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+                let amount = small_exponent(exponent(*rhs)?);
+                // The shift overflows if shifting back does not restore the value.
+                match self.checked_shl(amount).filter(|r| *r >> amount == *self) {
+                    Some(r) => return Ok(r),
+                    None if *self == 0 => return Ok(0),
+                    None => {}
+                }
+                match options.overflow {
+                    Overflow::Checked => Err(ArithError::Overflow),
+                    // `wrapping_shl` would take the amount modulo the width.
+                    Overflow::Wrapping if amount >= $t::BITS => Ok(0),
+                    Overflow::Wrapping => Ok(self.wrapping_shl(amount)),
+                    Overflow::Saturating if *self > 0 => Ok($t::MAX),
+                    Overflow::Saturating => Ok($t::MIN),
+                }
+            }
+
+            fn shr(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+                let amount = small_exponent(exponent(*rhs)?);
+                match options.overflow {
+                    _ if amount < $t::BITS => Ok(*self >> amount),
+                    Overflow::Checked => Err(ArithError::Overflow),
+                    // Every bit is shifted out: 0, or -1 for negative signed values.
+                    Overflow::Wrapping | Overflow::Saturating => Ok((*self >> ($t::BITS - 1)) >> 1),
+                }
+            }
+        }
+    };
+}
+
+impl_integer! { signed i8 i16 i32 i64 i128 isize }
+impl_integer! { unsigned u8 u16 u32 u64 u128 usize }
+
+macro_rules! impl_float {
+    ($($t:ident)*) => {$(
+        impl Number for $t {
+            fn zero() -> Self {
+                0.0
+            }
+
+            fn one() -> Self {
+                1.0
+            }
+
+            fn add(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+                float_result(*self + *rhs, &[*self, *rhs], options)
+            }
+
+            fn sub(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+                float_result(*self - *rhs, &[*self, *rhs], options)
+            }
+
+            fn mul(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+                float_result(*self * *rhs, &[*self, *rhs], options)
+            }
+
+            fn div(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+                if *rhs == 0.0 {
+                    return Err(ArithError::DivisionByZero);
+                }
+                let quotient = match options.division {
+                    Division::Truncating => *self / *rhs,
+                    Division::Euclidean => self.div_euclid(*rhs),
+                };
+                float_result(quotient, &[*self, *rhs], options)
+            }
+
+            fn rem(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+                if *rhs == 0.0 {
+                    return Err(ArithError::DivisionByZero);
+                }
+                match options.division {
+                    Division::Truncating => Ok(*self % *rhs),
+                    Division::Euclidean => Ok(self.rem_euclid(*rhs)),
+                }
+            }
+
+            fn pow(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+                float_result(self.powf(*rhs), &[*self, *rhs], options)
+            }
+
+            fn neg(&self, _options: &EvalOptions) -> Result<Self, ArithError> {
+                Ok(-*self)
+            }
+
+            fn abs(&self, _options: &EvalOptions) -> Result<Self, ArithError> {
+                Ok($t::abs(*self))
+            }
+
+            fn min(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+                Ok($t::min(*self, *rhs))
+            }
+
+            fn max(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+                Ok($t::max(*self, *rhs))
+            }
+
+            /// NaN is unordered, so comparing it is out of the domain.
+            fn compare(&self, rhs: &Self) -> Result<cmp::Ordering, ArithError> {
+                self.partial_cmp(rhs).ok_or(ArithError::Domain)
+            }
+
+            fn signed_zero() -> bool {
+                true
+            }
+        }
+    )*};
+}
+
+impl_float! { f32 f64 }
+
+/// Applies the overflow policy to a float result: a finite computation that
+/// yields an infinity has overflowed. `Wrapping` keeps IEEE semantics.
+fn float_result<F: Float>(
+    result: F,
+    operands: &[F],
+    options: &EvalOptions,
+) -> Result<F, ArithError> {
+    if !result.is_infinite() || operands.iter().any(|x| x.is_infinite()) {
+        return Ok(result);
+    }
+    match options.overflow {
+        Overflow::Checked => Err(ArithError::Overflow),
+        Overflow::Wrapping => Ok(result),
+        Overflow::Saturating => Ok(if result.is_sign_positive() {
+            F::MAX
+        } else {
+            F::MIN
+        }),
+    }
+}
+
+trait Float: Copy {
+    const MAX: Self;
+    const MIN: Self;
+    fn is_infinite(self) -> bool;
+    fn is_sign_positive(self) -> bool;
+}
+
+macro_rules! impl_float_consts {
+    ($($t:ident)*) => {$(
+        impl Float for $t {
+            const MAX: Self = $t::MAX;
+            const MIN: Self = $t::MIN;
+            fn is_infinite(self) -> bool {
+                $t::is_infinite(self)
+            }
+            fn is_sign_positive(self) -> bool {
+                $t::is_sign_positive(self)
+            }
+        }
+    )*};
+}
+
+impl_float_consts! { f32 f64 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::{eval, eval_with_options, EvalError, Operator, Token};
+    use super::*;
+
+    #[test]
+    fn test_generic_eval() {
+        let half = [
+            Token::Operand(1.0),
+            Token::Operand(2.0),
+            Token::Operator(Operator::Div),
+        ];
+        assert_eq!(eval::<f64>(&half), Ok(0.5));
+
+        let big = [
+            Token::Operand(i64::MAX as i128),
+            Token::Operand(4),
+            Token::Operator(Operator::Mul),
+        ];
+        assert_eq!(eval(&big), Ok(i64::MAX as i128 * 4));
+
+        let below_zero = [
+            Token::Operand(1u64),
+            Token::Operand(2),
+            Token::Operator(Operator::Sub),
+        ];
+        assert_eq!(eval(&below_zero), Err(EvalError::Overflow { index: 2 }));
+        let wrapping = EvalOptions::new(Overflow::Wrapping, Division::Truncating);
+        assert_eq!(eval_with_options(&below_zero, &wrapping), Ok(u64::MAX));
+    }
+
+    #[test]
+    fn test_shift_overflow() {
+        let shl = |x: isize, amount: isize| {
+            [
+                Token::Operand(x),
+                Token::Operand(amount),
+                Token::Operator(Operator::Shl),
+            ]
+        };
+        let saturating = EvalOptions::new(Overflow::Saturating, Division::Truncating);
+        let wrapping = EvalOptions::new(Overflow::Wrapping, Division::Truncating);
+
+        assert_eq!(eval(&shl(1, 62)), Ok(1 << 62));
+        assert_eq!(eval(&shl(-1, 63)), Ok(isize::MIN));
+        assert_eq!(eval(&shl(0, 100)), Ok(0));
+        assert_eq!(eval(&shl(1, 63)), Err(EvalError::Overflow { index: 2 }));
+        assert_eq!(eval(&shl(3, 62)), Err(EvalError::Overflow { index: 2 }));
+        assert_eq!(eval_with_options(&shl(3, 62), &wrapping), Ok(3 << 62));
+        assert_eq!(eval_with_options(&shl(1, 100), &saturating), Ok(isize::MAX));
+        assert_eq!(eval_with_options(&shl(-3, 62), &saturating), Ok(isize::MIN));
+
+        // Shifting by the width or more shifts every bit out.
+        fn shift<N>(x: N, op: Operator, amount: N) -> [Token<N>; 3] {
+            [
+                Token::Operand(x),
+                Token::Operand(amount),
+                Token::Operator(op),
+            ]
+        }
+        for &amount in &[8u8, 9, 200] {
+            let shl = shift(1u8, Operator::Shl, amount);
+            assert_eq!(eval_with_options(&shl, &wrapping), Ok(0));
+            let shr = shift(0x80u8, Operator::Shr, amount);
+            assert_eq!(eval_with_options(&shr, &wrapping), Ok(0));
+        }
+        for &amount in &[8i8, 9, 100] {
+            let shl = shift(-1i8, Operator::Shl, amount);
+            assert_eq!(eval_with_options(&shl, &wrapping), Ok(0));
+            let shr = shift(-128i8, Operator::Shr, amount);
+            assert_eq!(eval_with_options(&shr, &wrapping), Ok(-1));
+            let shr = shift(64i8, Operator::Shr, amount);
+            assert_eq!(eval_with_options(&shr, &wrapping), Ok(0));
+        }
+        let shl = shift(1u8, Operator::Shl, 7);
+        assert_eq!(eval_with_options(&shl, &wrapping), Ok(0x80));
+    }
+
+    #[test]
+    fn test_large_exponents() {
+        let big = 5_000_000_000;
+        let binary = |x: isize, op: Operator, amount: isize| {
+            [
+                Token::Operand(x),
+                Token::Operand(amount),
+                Token::Operator(op),
+            ]
+        };
+        let saturating = EvalOptions::new(Overflow::Saturating, Division::Truncating);
+        let wrapping = EvalOptions::new(Overflow::Wrapping, Division::Truncating);
+
+        assert_eq!(eval(&binary(1, Operator::Pow, big)), Ok(1));
+        assert_eq!(eval(&binary(-1, Operator::Pow, big)), Ok(1));
+        assert_eq!(eval(&binary(-1, Operator::Pow, big + 1)), Ok(-1));
+        assert_eq!(eval(&binary(0, Operator::Pow, big)), Ok(0));
+        assert_eq!(
+            eval(&binary(2, Operator::Pow, big)),
+            Err(EvalError::Overflow { index: 2 })
+        );
+        let pow = binary(-2, Operator::Pow, big + 1);
+        assert_eq!(eval_with_options(&pow, &saturating), Ok(isize::MIN));
+        assert_eq!(eval_with_options(&pow, &wrapping), Ok(0));
+        // 3 ** big = (3 ** 2^30) ** 4 * 3 ** (big - 2^32)
+        let pow = binary(3, Operator::Pow, big);
+        assert_eq!(
+            eval_with_options(&pow, &wrapping),
+            Ok(3isize
+                .wrapping_pow(1 << 30)
+                .wrapping_pow(4)
+                .wrapping_mul(3isize.wrapping_pow((big - (1 << 32)) as u32)))
+        );
+
+        let shl = binary(1, Operator::Shl, big);
+        assert_eq!(eval_with_options(&shl, &saturating), Ok(isize::MAX));
+        assert_eq!(eval_with_options(&shl, &wrapping), Ok(0));
+        let shr = binary(-8, Operator::Shr, big);
+        assert_eq!(eval_with_options(&shr, &saturating), Ok(-1));
+        assert_eq!(eval(&shr), Err(EvalError::Overflow { index: 2 }));
+    }
+
+    #[test]
+    fn test_float_overflow() {
+        let tokens = [
+            Token::Operand(f64::MAX),
+            Token::Operand(2.0),
+            Token::Operator(Operator::Mul),
+        ];
+        let saturating = EvalOptions::new(Overflow::Saturating, Division::Truncating);
+        assert_eq!(eval(&tokens), Err(EvalError::Overflow { index: 2 }));
+        assert_eq!(eval_with_options(&tokens, &saturating), Ok(f64::MAX));
+    }
+}
@@ -0,0 +1,246 @@
+use super::{ArithError, Division, EvalError, EvalOptions, Expr, Number, Operator, Token};
+
+/// Rewrites a postfix expression into an equivalent one that is no longer,
+/// for evaluation with the default options.
+pub fn optimize<N: Number + PartialEq>(tokens: &[Token<N>]) -> Result<Vec<Token<N>>, EvalError> {
+    optimize_with_options(tokens, &EvalOptions::default())
+}
+
+/// Rewrites a postfix expression into an equivalent one that is no longer,
+/// for evaluation with `options`.
+///
+/// Constant subexpressions are folded and identities such as `x 0 +`,
+/// `x 1 *` and `0 x -` are simplified; see `Expr::simplify`. Stack words are
+/// resolved, which can duplicate subexpressions, so if the rewrite is not
+/// shorter the tokens are returned as they are. To also turn `x x -` into
+/// `0`, use `optimize_assuming_bound`.
+pub fn optimize_with_options<N: Number + PartialEq>(
+    tokens: &[Token<N>],
+    options: &EvalOptions,
+) -> Result<Vec<Token<N>>, EvalError> {
+    shorter(tokens, Expr::from_postfix(tokens)?.simplify(options))
+}
+
+/// Rewrites a postfix expression like `optimize_with_options`, assuming that
+/// every variable will be bound when it is evaluated, and for floats bound to
+/// a finite value, as with `eval_with` over complete records.
+///
+/// This also drops variables where their value cannot matter: `x x -` and
+/// `x 0 *` become `0`, and `x 0 **` becomes `1`. See
+/// `Expr::simplify_assuming_bound`.
+pub fn optimize_assuming_bound<N: Number + PartialEq>(
+    tokens: &[Token<N>],
+    options: &EvalOptions,
+) -> Result<Vec<Token<N>>, EvalError> {
+    shorter(
+        tokens,
+        Expr::from_postfix(tokens)?.simplify_assuming_bound(options),
+    )
+}
+
+/// Returns the tokens of `optimized` if they are fewer than `tokens`.
+fn shorter<N: Clone>(tokens: &[Token<N>], optimized: Expr<N>) -> Result<Vec<Token<N>>, EvalError> {
+    let optimized = optimized.to_postfix();
+    if optimized.len() < tokens.len() {
+        Ok(optimized)
+    } else {
+        Ok(tokens.to_vec())
+    }
+}
+
+impl<N: Number + PartialEq> Expr<N> {
+    /// Returns the tree with constant subexpressions folded and identities
+    /// applied, working from the leaves up.
+    ///
+    /// The result evaluates like `self` under `options`, errors included: an
+    /// operation that fails is not folded, and only numbers are ever dropped.
+    /// Variables are kept, since they may be unbound, so `x x -` and `x 0 *`
+    /// stay as they are; see `simplify_assuming_bound`. For types with a signed zero, such as floats, adding
+    /// 0 and `0 x -` are kept too, since they turn -0 into +0 and +0 into -0.
+    /// An operator with the wrong number of arguments is left as it is.
+    pub fn simplify(&self, options: &EvalOptions) -> Expr<N> {
+        self.simplify_with(options, false)
+    }
+
+    /// Returns the tree simplified like `simplify`, assuming that every
+    /// variable will be bound, and for floats bound to a finite value.
+    ///
+    /// A variable is then dropped where its value cannot matter, as in
+    /// `x x -`, `x 0 *` (except for floats, where it may be -0) and `x 0 **`.
+    /// Subexpressions other than variables and numbers are still kept, so
+    /// errors such as a division by zero do not go away.
+    pub fn simplify_assuming_bound(&self, options: &EvalOptions) -> Expr<N> {
+        self.simplify_with(options, true)
+    }
+
+    fn simplify_with(&self, options: &EvalOptions, bound: bool) -> Expr<N> {
+        match *self {
+            Expr::Apply(op, ref args) => {
+                let args: Vec<Expr<N>> = args
+                    .iter()
+                    .map(|arg| arg.simplify_with(options, bound))
+                    .collect();
+                if args.len() != op.arity() {
+                    return Expr::Apply(op, args);
+                }
+                match fold(op, &args, options).or_else(|| identity(op, &args, options, bound)) {
+                    Some(simplified) => simplified,
+                    None => Expr::Apply(op, args),
+                }
+            }
+            _ => self.clone(),
+        }
+    }
+}
+
+/// Computes `op` if all of `args` are numbers and the operation succeeds.
+fn fold<N: Number>(op: Operator, args: &[Expr<N>], options: &EvalOptions) -> Option<Expr<N>> {
+    let values = args
+        .iter()
+        .map(|arg| match *arg {
+            Expr::Number(ref value) => Some(value.clone()),
+            _ => None,
+        })
+        .collect::<Option<Vec<N>>>()?;
+    op.apply(&values, options).ok().map(Expr::Number)
+}
+
+/// Applies an identity of the binary operator `op` to `args`. Identities
+/// that drop a variable apply only if variables are assumed to be `bound`.
+fn identity<N: Number + PartialEq>(
+    op: Operator,
+    args: &[Expr<N>],
+    options: &EvalOptions,
+    bound: bool,
+) -> Option<Expr<N>> {
+    let (zero, one) = (N::zero(), N::one());
+    // Rewriting an operation the type lacks would hide the error.
+    let supported = |op: Operator| {
+        op.apply(&vec![one.clone(); op.arity()], options) != Err(ArithError::Unsupported)
+    };
+    if op.arity() != 2 || !supported(op) {
+        return None;
+    }
+    let is = |expr: &Expr<N>, n: &N| match *expr {
+        Expr::Number(ref value) => value == n,
+        _ => false,
+    };
+    let droppable = |expr: &Expr<N>| bound && !matches!(*expr, Expr::Apply(..));
+
+    let (x, y) = (&args[0], &args[1]);
+    let simplified = match op {
+        // With a signed zero, -0 + 0 is +0, and 0 - 0 is not -0.
+        Operator::Add if is(y, &zero) && !N::signed_zero() => x.clone(),
+        Operator::Add if is(x, &zero) && !N::signed_zero() => y.clone(),
+        Operator::Sub if is(y, &zero) => x.clone(),
+        Operator::Sub if is(x, &zero) && !N::signed_zero() && supported(Operator::Neg) => {
+            Expr::Apply(Operator::Neg, vec![y.clone()])
+        }
+        Operator::Mul if is(y, &one) => x.clone(),
+        Operator::Mul if is(x, &one) => y.clone(),
+        Operator::Sub if x == y && droppable(x) => Expr::Number(zero),
+        Operator::Mul
+            if !N::signed_zero()
+                && (is(x, &zero) && droppable(y) || is(y, &zero) && droppable(x)) =>
+        {
+            Expr::Number(zero)
+        }
+        // Euclidean division rounds a float quotient down, even by 1.
+        Operator::Div if is(y, &one) && options.division == Division::Truncating => x.clone(),
+        Operator::Pow if is(y, &one) => x.clone(),
+        Operator::Pow if is(y, &zero) && droppable(x) => Expr::Number(one),
+        _ => return None,
+    };
+    Some(simplified)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{eval_with, tokenize, NoVariables};
+    use super::*;
+
+    fn optimized(source: &str) -> Vec<Token> {
+        optimize(&tokenize(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_optimize() {
+        assert_eq!(optimized("x 0 + 1 *"), tokenize("x").unwrap());
+        assert_eq!(optimized("2 3 + x *"), tokenize("5 x *").unwrap());
+        assert_eq!(optimized("x x - 2 2 * +"), tokenize("x x - 4 +").unwrap());
+        assert_eq!(optimized("0 x 2 ** -"), tokenize("x 2 ** neg").unwrap());
+
+        let tokens = tokenize("x 4 2 3 * - * y 1 ** +").unwrap();
+        let env = [("x", 5), ("y", 7)];
+        let shorter = optimize(&tokens).unwrap();
+        assert_eq!(shorter, tokenize("x -2 * y +").unwrap());
+        assert_eq!(eval_with(&shorter, &env[..]), eval_with(&tokens, &env[..]));
+    }
+
+    #[test]
+    fn test_optimize_assuming_bound() {
+        let options = EvalOptions::default();
+        let tokens = tokenize("x x - y 0 * + z 0 ** *").unwrap();
+        let shorter = optimize_assuming_bound(&tokens, &options).unwrap();
+        assert_eq!(shorter, tokenize("0").unwrap());
+        let env = [("x", 5), ("y", 7), ("z", 9)];
+        assert_eq!(eval_with(&shorter, &env[..]), eval_with(&tokens, &env[..]));
+
+        // Only variables are dropped, never a failing subexpression.
+        let tokens = tokenize("1 0 / dup - x +").unwrap();
+        assert_eq!(optimize_assuming_bound(&tokens, &options).unwrap(), tokens);
+    }
+
+    #[test]
+    fn test_simplify_malformed() {
+        let malformed = Expr::Apply(
+            Operator::Add,
+            vec![Expr::Apply(
+                Operator::Mul,
+                vec![Expr::Number(2), Expr::Number(3)],
+            )],
+        );
+        assert_eq!(
+            malformed.simplify(&EvalOptions::default()),
+            Expr::Apply(Operator::Add, vec![Expr::Number(6)])
+        );
+    }
+
+    #[test]
+    fn test_optimize_keeps_errors() {
+        // The failing division is kept; only `2 0 *` goes.
+        assert_eq!(optimized("1 0 / 2 0 * +"), tokenize("1 0 /").unwrap());
+        // Dropping `x` would hide that it may be unbound.
+        let tokens = tokenize("x x - y 0 * +").unwrap();
+        assert_eq!(optimize(&tokens).unwrap(), tokens);
+        assert_eq!(
+            eval_with(&optimized("x x - 0 +"), &NoVariables),
+            Err(EvalError::UnboundVariable { index: 0 })
+        );
+        // For floats, `x 0 +` is +0 for x = -0.
+        let floats = [
+            Token::Variable("x".to_string()),
+            Token::Operand(0.0),
+            Token::Operator(Operator::Add),
+            Token::Operand(1.0),
+            Token::Operator(Operator::Mul),
+        ];
+        let optimized = optimize(&floats).unwrap();
+        assert_eq!(optimized, &floats[..3]);
+        let env = [("x", -0.0f64)];
+        assert!(!eval_with(&optimized, &env[..]).unwrap().is_sign_negative());
+        let floats = [
+            Token::Operand(0.0),
+            Token::Variable("x".to_string()),
+            Token::Operator(Operator::Sub),
+        ];
+        assert_eq!(optimize(&floats).unwrap(), floats);
+        // Dropping `1 0 /` would hide the error.
+        let tokens = tokenize("1 0 / dup -").unwrap();
+        assert_eq!(optimize(&tokens).unwrap(), tokens);
+        assert_eq!(
+            optimize(&tokenize("1 +").unwrap()),
+            Err(EvalError::StackUnderflow { index: 1, depth: 1 })
+        );
+    }
+}
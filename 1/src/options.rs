@@ -0,0 +1,82 @@
+/// Selects what happens when an arithmetic result does not fit in its type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Reports `EvalError::Overflow`.
+    Checked,
+    /// Wraps around at the boundary of the type (two's complement).
+    Wrapping,
+    /// Clamps to the minimum or maximum value of the type.
+    Saturating,
+}
+
+/// Selects how integer division rounds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Division {
+    /// Rounds towards zero, like Rust's `/`: `-7 / 2 == -3`.
+    Truncating,
+    /// Keeps the remainder non-negative: `-7 / 2 == -4`.
+    Euclidean,
+}
+
+/// Bounds on the work and memory an expression may use, for evaluating
+/// untrusted input. Every limit defaults to unlimited, so set only the ones
+/// you need and fill in the rest with `..Limits::default()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Most tokens an expression may consist of.
+    pub max_tokens: usize,
+    /// Most values the stack may hold at once.
+    pub max_depth: usize,
+    /// Most tokens evaluated in total, counting every token run inside a word
+    /// and every call of a word.
+    pub max_steps: usize,
+    /// Most tokens an `Interpreter` stores across the bodies of all its words.
+    pub max_word_tokens: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_tokens: usize::MAX,
+            max_depth: usize::MAX,
+            max_steps: usize::MAX,
+            max_word_tokens: usize::MAX,
+        }
+    }
+}
+
+/// Controls the arithmetic semantics and limits used by `eval_with_options`.
+///
+/// The default is `Overflow::Checked` and `Division::Truncating` without
+/// limits, which is what `eval` uses. The results do not depend on the build
+/// profile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EvalOptions {
+    pub overflow: Overflow,
+    pub division: Division,
+    pub limits: Limits,
+}
+
+impl EvalOptions {
+    /// Creates options with the given overflow and division semantics and no
+    /// limits.
+    pub fn new(overflow: Overflow, division: Division) -> Self {
+        EvalOptions {
+            overflow,
+            division,
+            limits: Limits::default(),
+        }
+    }
+
+    /// Returns the options with `limits` instead.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+}
+
+impl Default for EvalOptions {
+    fn default() -> Self {
+        EvalOptions::new(Overflow::Checked, Division::Truncating)
+    }
+}
@@ -0,0 +1,209 @@
+use super::{analyze, Environment, EvalError, EvalOptions, Number, Operator, StackOp, Token};
+
+/// One step of a compiled program. Instruction `i` comes from token `i`.
+#[derive(Clone, Debug, PartialEq)]
+enum Instr<N> {
+    Push(N),
+    /// Pushes the value of the variable in the given slot.
+    Load(usize),
+    Apply(Operator),
+    Stack(StackOp),
+}
+
+/// A postfix expression that has been validated once and can be run many
+/// times.
+///
+/// Running never checks the stack shape again, and does not allocate when
+/// given a stack from `Program::stack` (or any `Vec` with at least
+/// `max_depth()` capacity).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program<N = isize> {
+    code: Vec<Instr<N>>,
+    variables: Vec<String>,
+    max_depth: usize,
+    options: EvalOptions,
+}
+
+/// Validates `tokens` and compiles them into a `Program` that computes with
+/// the default `EvalOptions`.
+pub fn compile<N: Clone>(tokens: &[Token<N>]) -> Result<Program<N>, EvalError> {
+    let max_depth = analyze(tokens)?;
+    let mut variables: Vec<String> = Vec::new();
+    let mut code = Vec::with_capacity(tokens.len());
+
+    for token in tokens {
+        code.push(match *token {
+            Token::Operand(ref value) => Instr::Push(value.clone()),
+            Token::Operator(op) => Instr::Apply(op),
+            Token::Stack(op) => Instr::Stack(op),
+            Token::Variable(ref name) => match variables.iter().position(|v| v == name) {
+                Some(slot) => Instr::Load(slot),
+                None => {
+                    variables.push(name.clone());
+                    Instr::Load(variables.len() - 1)
+                }
+            },
+            // `analyze` rejects definitions.
+            Token::Define | Token::End => unreachable!(),
+        });
+    }
+
+    Ok(Program {
+        code,
+        variables,
+        max_depth,
+        options: EvalOptions::default(),
+    })
+}
+
+impl<N: Number> Program<N> {
+    /// Returns the program computing with `options` instead.
+    pub fn with_options(mut self, options: EvalOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Returns the largest number of values the stack holds while running.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Returns the names of the variables the program reads, in slot order:
+    /// the order in which they first appear.
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    /// Returns an empty stack large enough to run the program without
+    /// allocating.
+    pub fn stack(&self) -> Vec<N> {
+        Vec::with_capacity(self.max_depth)
+    }
+
+    /// Runs the program on `stack`, looking up variables in `env`.
+    pub fn run<E: Environment<N> + ?Sized>(
+        &self,
+        env: &E,
+        stack: &mut Vec<N>,
+    ) -> Result<N, EvalError> {
+        self.exec(stack, |slot| env.get(&self.variables[slot]))
+    }
+
+    /// Runs the program on `stack` with `bindings[i]` as the value of the
+    /// variable `self.variables()[i]`. Panics if `bindings` has the wrong
+    /// length.
+    pub fn run_bound(&self, bindings: &[N], stack: &mut Vec<N>) -> Result<N, EvalError> {
+        assert_eq!(bindings.len(), self.variables.len());
+        self.exec(stack, |slot| Some(bindings[slot].clone()))
+    }
+
+    fn exec<F: Fn(usize) -> Option<N>>(&self, stack: &mut Vec<N>, load: F) -> Result<N, EvalError> {
+        self.check_limits()?;
+        stack.clear();
+        for (index, instr) in self.code.iter().enumerate() {
+            match *instr {
+                Instr::Push(ref value) => stack.push(value.clone()),
+                Instr::Load(slot) => match load(slot) {
+                    Some(value) => stack.push(value),
+                    None => return Err(EvalError::UnboundVariable { index }),
+                },
+                Instr::Apply(op) => {
+                    let at = stack.len() - op.arity();
+                    let result = op
+                        .apply(&stack[at..], &self.options)
+                        .map_err(|e| EvalError::at(e, index))?;
+                    stack.truncate(at);
+                    stack.push(result);
+                }
+                Instr::Stack(op) => op.apply(stack),
+            }
+        }
+        Ok(stack.pop().unwrap())
+    }
+
+    /// Checks the limits in the options up front: the size and depth of the
+    /// program are known, and every instruction is one step.
+    fn check_limits(&self) -> Result<(), EvalError> {
+        let limits = &self.options.limits;
+        if self.code.len() > limits.max_tokens {
+            return Err(EvalError::TooManyTokens {
+                index: limits.max_tokens,
+            });
+        }
+        if self.code.len() > limits.max_steps {
+            return Err(EvalError::StepLimit {
+                index: limits.max_steps,
+            });
+        }
+        if self.max_depth <= limits.max_depth {
+            return Ok(());
+        }
+        let mut depth = 0;
+        for (index, instr) in self.code.iter().enumerate() {
+            let (pops, pushes) = match *instr {
+                Instr::Push(_) | Instr::Load(_) => (0, 1),
+                Instr::Apply(op) => (op.arity(), 1),
+                Instr::Stack(op) => op.effect(),
+            };
+            depth = depth - pops + pushes;
+            if depth > limits.max_depth {
+                return Err(EvalError::StackLimit { index });
+            }
+        }
+        unreachable!()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{tokenize, Limits};
+    use super::*;
+
+    #[test]
+    fn test_program() {
+        let program = compile(&tokenize("price qty * price dup * +").unwrap()).unwrap();
+        assert_eq!(program.max_depth(), 3);
+        assert_eq!(
+            program.variables(),
+            &["price".to_string(), "qty".to_string()][..]
+        );
+
+        let mut stack = program.stack();
+        assert_eq!(program.run_bound(&[2, 5], &mut stack), Ok(14));
+        assert_eq!(program.run_bound(&[3, 1], &mut stack), Ok(12));
+        assert_eq!(
+            program.run(&[("qty", 1), ("price", 3)][..], &mut stack),
+            Ok(12)
+        );
+        assert_eq!(
+            program.run(&[("price", 3)][..], &mut stack),
+            Err(EvalError::UnboundVariable { index: 1 })
+        );
+        assert_eq!(stack.capacity(), 3);
+    }
+
+    #[test]
+    fn test_compile_errors() {
+        assert_eq!(
+            compile(&tokenize("1 + 2 3").unwrap()),
+            Err(EvalError::StackUnderflow { index: 1, depth: 1 })
+        );
+        let program = compile(&tokenize("x 0 /").unwrap()).unwrap();
+        assert_eq!(
+            program.run_bound(&[1], &mut program.stack()),
+            Err(EvalError::DivisionByZero { index: 2 })
+        );
+
+        let limits = Limits {
+            max_depth: 2,
+            ..Limits::default()
+        };
+        let program = compile(&tokenize("x 1 2 + +").unwrap())
+            .unwrap()
+            .with_options(EvalOptions::default().with_limits(limits));
+        assert_eq!(
+            program.run_bound(&[1], &mut program.stack()),
+            Err(EvalError::StackLimit { index: 2 })
+        );
+    }
+}
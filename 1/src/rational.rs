@@ -0,0 +1,273 @@
+use std::cmp::{self, Ordering};
+use std::fmt;
+
+use super::{ArithError, Division, EvalError, EvalOptions, Number, Token};
+
+/// An exact fraction `numer / denom`.
+///
+/// Always stored in lowest terms with a positive denominator, so two equal
+/// fractions compare equal field by field. Operations that would leave the
+/// range of `isize` report `ArithError::Overflow` instead of rounding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Rational {
+    numer: isize,
+    denom: isize,
+}
+
+impl Rational {
+    /// Creates the fraction `numer / denom` in lowest terms.
+    pub fn new(numer: isize, denom: isize) -> Result<Rational, ArithError> {
+        Rational::reduce(numer as i128, denom as i128)
+    }
+
+    /// Creates the fraction `n / 1`.
+    pub fn from_integer(n: isize) -> Rational {
+        Rational { numer: n, denom: 1 }
+    }
+
+    /// Returns the numerator, which carries the sign.
+    pub fn numer(&self) -> isize {
+        self.numer
+    }
+
+    /// Returns the denominator, which is always positive.
+    pub fn denom(&self) -> isize {
+        self.denom
+    }
+
+    /// Returns `true` if the denominator is 1.
+    pub fn is_integer(&self) -> bool {
+        self.denom == 1
+    }
+
+    /// Normalizes `numer / denom` computed in `i128` and narrows it back to
+    /// `isize`.
+    fn reduce(numer: i128, denom: i128) -> Result<Rational, ArithError> {
+        if denom == 0 {
+            return Err(ArithError::DivisionByZero);
+        }
+        let divisor = gcd(numer, denom);
+        let (mut numer, mut denom) = (numer / divisor, denom / divisor);
+        if denom < 0 {
+            numer = -numer;
+            denom = -denom;
+        }
+        if numer < isize::MIN as i128 || numer > isize::MAX as i128 || denom > isize::MAX as i128 {
+            return Err(ArithError::Overflow);
+        }
+        Ok(Rational {
+            numer: numer as isize,
+            denom: denom as isize,
+        })
+    }
+}
+
+/// Greatest common divisor; never zero as long as `b` is not zero.
+fn gcd(mut a: i128, mut b: i128) -> i128 {
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    a.abs()
+}
+
+impl From<isize> for Rational {
+    fn from(n: isize) -> Rational {
+        Rational::from_integer(n)
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Rational) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Rational) -> Ordering {
+        // Denominators are positive, so cross-multiplying keeps the order.
+        let lhs = self.numer as i128 * other.denom as i128;
+        let rhs = other.numer as i128 * self.denom as i128;
+        lhs.cmp(&rhs)
+    }
+}
+
+impl fmt::Display for Rational {
+    /// Writes `numer/denom`, or just `numer` for whole numbers.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_integer() {
+            write!(f, "{}", self.numer)
+        } else {
+            write!(f, "{}/{}", self.numer, self.denom)
+        }
+    }
+}
+
+/// Rational arithmetic is always exact, and a result outside the range of
+/// `isize` is always an overflow error whatever `options.overflow` says.
+/// `options.division` only picks the sign of a `%` remainder: truncating
+/// takes the sign of the dividend, Euclidean is never negative.
+impl Number for Rational {
+    fn zero() -> Self {
+        Rational::from_integer(0)
+    }
+
+    fn one() -> Self {
+        Rational::from_integer(1)
+    }
+
+    fn add(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        let (a, b, c, d) = widen(self, rhs);
+        Rational::reduce(a * d + c * b, b * d)
+    }
+
+    fn sub(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        let (a, b, c, d) = widen(self, rhs);
+        Rational::reduce(a * d - c * b, b * d)
+    }
+
+    fn mul(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        let (a, b, c, d) = widen(self, rhs);
+        Rational::reduce(a * c, b * d)
+    }
+
+    fn div(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        let (a, b, c, d) = widen(self, rhs);
+        Rational::reduce(a * d, b * c)
+    }
+
+    fn rem(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+        let quotient = self.div(rhs, options)?;
+        let whole = Rational::from_integer(quotient.numer / quotient.denom);
+        let rem = self.sub(&rhs.mul(&whole, options)?, options)?;
+        if options.division == Division::Euclidean && rem.numer < 0 {
+            rem.add(&rhs.abs(options)?, options)
+        } else {
+            Ok(rem)
+        }
+    }
+
+    /// Only whole exponents are in the domain; negative ones take the
+    /// reciprocal.
+    fn pow(&self, rhs: &Self, options: &EvalOptions) -> Result<Self, ArithError> {
+        if !rhs.is_integer() {
+            return Err(ArithError::Domain);
+        }
+        let mut base = if rhs.numer < 0 {
+            Rational::from_integer(1).div(self, options)?
+        } else {
+            *self
+        };
+        let mut exp = rhs.numer.unsigned_abs();
+        let mut result = Rational::from_integer(1);
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = result.mul(&base, options)?;
+            }
+            exp >>= 1;
+            if exp > 0 {
+                base = base.mul(&base, options)?;
+            }
+        }
+        Ok(result)
+    }
+
+    fn neg(&self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Rational::reduce(-(self.numer as i128), self.denom as i128)
+    }
+
+    fn abs(&self, options: &EvalOptions) -> Result<Self, ArithError> {
+        if self.numer < 0 {
+            self.neg(options)
+        } else {
+            Ok(*self)
+        }
+    }
+
+    fn min(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Ok(cmp::min(*self, *rhs))
+    }
+
+    fn max(&self, rhs: &Self, _options: &EvalOptions) -> Result<Self, ArithError> {
+        Ok(cmp::max(*self, *rhs))
+    }
+
+    fn compare(&self, rhs: &Self) -> Result<Ordering, ArithError> {
+        Ok(self.cmp(rhs))
+    }
+}
+
+/// Returns `(a, b, c, d)` for the fractions `a/b` and `c/d`. Products of two
+/// `isize` values always fit in `i128`.
+fn widen(x: &Rational, y: &Rational) -> (i128, i128, i128, i128) {
+    (
+        x.numer as i128,
+        x.denom as i128,
+        y.numer as i128,
+        y.denom as i128,
+    )
+}
+
+/// Evaluates the postfix expression with exact fractions, so division never
+/// truncates: `1 2 / 2 *` is exactly 1.
+pub fn eval_exact(tokens: &[Token]) -> Result<Rational, EvalError> {
+    let tokens: Vec<Token<Rational>> = tokens.iter().map(|t| t.map(|&n| n.into())).collect();
+    super::eval(&tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Operator;
+    use super::*;
+
+    #[test]
+    fn test_rational() {
+        assert_eq!(Rational::new(6, -4), Ok(Rational::new(-3, 2).unwrap()));
+        assert_eq!(Rational::new(-3, 2).unwrap().to_string(), "-3/2");
+        assert_eq!(Rational::new(4, 2).unwrap().to_string(), "2");
+        assert_eq!(Rational::new(1, 0), Err(ArithError::DivisionByZero));
+        assert_eq!(Rational::new(isize::MIN, -1), Err(ArithError::Overflow));
+        assert!(Rational::new(1, 3).unwrap() < Rational::new(1, 2).unwrap());
+
+        let options = EvalOptions::default();
+        let two_thirds = Rational::new(2, 3).unwrap();
+        let minus_seven_halves = Rational::new(-7, 2).unwrap();
+        assert_eq!(
+            two_thirds.pow(&Rational::from_integer(-2), &options),
+            Rational::new(9, 4)
+        );
+        assert_eq!(
+            minus_seven_halves.rem(&two_thirds, &options),
+            Rational::new(-1, 6)
+        );
+        let euclid = EvalOptions::new(options.overflow, Division::Euclidean);
+        assert_eq!(
+            minus_seven_halves.rem(&two_thirds, &euclid),
+            Rational::new(1, 2)
+        );
+    }
+
+    #[test]
+    fn test_eval_exact() {
+        let tokens = [
+            Token::Operand(1),
+            Token::Operand(2),
+            Token::Operator(Operator::Div),
+            Token::Operand(2),
+            Token::Operator(Operator::Mul),
+        ];
+        assert_eq!(eval_exact(&tokens), Ok(Rational::from_integer(1)));
+
+        let tokens = [
+            Token::Operand(1),
+            Token::Operand(3),
+            Token::Operator(Operator::Div),
+            Token::Operand(1),
+            Token::Operand(6),
+            Token::Operator(Operator::Div),
+            Token::Operator(Operator::Add),
+        ];
+        assert_eq!(eval_exact(&tokens), Ok(Rational::new(1, 2).unwrap()));
+    }
+}
@@ -0,0 +1,151 @@
+use super::machine::Machine;
+use super::{validate, Environment, EvalError, EvalOptions, NoVariables, Number, Token};
+
+/// What evaluating a single token did to the stack.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step<'a, N: 'a> {
+    /// Index of the token in the expression.
+    pub index: usize,
+    pub token: &'a Token<N>,
+    /// The values the token took off the stack, bottom first.
+    pub popped: Vec<N>,
+    /// The values the token put on the stack, bottom first.
+    pub pushed: Vec<N>,
+    /// The whole stack after the token, bottom first.
+    pub stack: Vec<N>,
+}
+
+/// Iterator over the evaluation of a postfix expression, one `Step` per
+/// token. Created by `trace` and `trace_with`.
+///
+/// The trace reports the same error as `eval`. A malformed expression, e.g.
+/// one that leaves more than one value, is rejected before any step, so the
+/// `Err` is the only item. Otherwise, if evaluation fails, the last item is
+/// the `Err`. After a successful run the result is the only value in the
+/// last step's `stack`.
+pub struct Trace<'a, N: 'a, E: ?Sized + 'a> {
+    tokens: &'a [Token<N>],
+    env: &'a E,
+    options: EvalOptions,
+    machine: Machine<N>,
+    next: usize,
+    done: bool,
+}
+
+/// Traces the evaluation of `tokens` with the default options.
+pub fn trace<N: Number>(tokens: &[Token<N>]) -> Trace<'_, N, NoVariables> {
+    trace_with(tokens, &NoVariables)
+}
+
+/// Traces the evaluation of `tokens`, looking up variables in `env`.
+pub fn trace_with<'a, N: Number, E: Environment<N> + ?Sized>(
+    tokens: &'a [Token<N>],
+    env: &'a E,
+) -> Trace<'a, N, E> {
+    Trace {
+        tokens,
+        env,
+        options: EvalOptions::default(),
+        machine: Machine::new(),
+        next: 0,
+        done: false,
+    }
+}
+
+impl<'a, N: Number, E: Environment<N> + ?Sized> Trace<'a, N, E> {
+    /// Returns the trace computing with `options` instead.
+    pub fn with_options(mut self, options: EvalOptions) -> Self {
+        self.options = options;
+        self
+    }
+}
+
+impl<'a, N: Number, E: Environment<N> + ?Sized> Iterator for Trace<'a, N, E> {
+    type Item = Result<Step<'a, N>, EvalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let index = self.next;
+        if index == 0 {
+            if let Err(e) = validate(self.tokens, &self.options) {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+        let token = match self.tokens.get(index) {
+            Some(token) => token,
+            None => {
+                self.done = true;
+                return self.machine.check_done().err().map(Err);
+            }
+        };
+        self.next += 1;
+
+        let (pops, pushes) = token.stack_effect().unwrap_or((0, 0));
+        let before = self.machine.values();
+        let popped = before[before.len().saturating_sub(pops)..].to_vec();
+        if let Err(e) = self.machine.step(token, index, self.env, &self.options) {
+            self.done = true;
+            return Some(Err(e));
+        }
+        let after = self.machine.values();
+        Some(Ok(Step {
+            index,
+            token,
+            popped,
+            pushed: after[after.len() - pushes..].to_vec(),
+            stack: after.to_vec(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{eval, tokenize, Operator, StackOp};
+    use super::*;
+
+    #[test]
+    fn test_trace() {
+        let tokens = tokenize("3 dup 4 + *").unwrap();
+        let steps: Vec<_> = trace(&tokens).collect();
+        assert_eq!(steps.len(), 5);
+        assert_eq!(
+            steps[1],
+            Ok(Step {
+                index: 1,
+                token: &Token::Stack(StackOp::Dup),
+                popped: vec![3],
+                pushed: vec![3, 3],
+                stack: vec![3, 3],
+            })
+        );
+        assert_eq!(
+            steps[3],
+            Ok(Step {
+                index: 3,
+                token: &Token::Operator(Operator::Add),
+                popped: vec![3, 4],
+                pushed: vec![7],
+                stack: vec![3, 7],
+            })
+        );
+        assert_eq!(steps[4].as_ref().unwrap().stack, vec![21]);
+    }
+
+    #[test]
+    fn test_trace_errors() {
+        let tokens = tokenize("1 0 / 2 +").unwrap();
+        let steps: Vec<_> = trace(&tokens).collect();
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[2], Err(EvalError::DivisionByZero { index: 2 }));
+
+        // Like `eval`, the trace rejects a malformed expression up front.
+        let tokens = tokenize("1 0 / 2").unwrap();
+        let steps: Vec<_> = trace(&tokens).collect();
+        let error = EvalError::LeftoverOperands { index: 3, depth: 2 };
+        assert_eq!(steps, vec![Err(error)]);
+        assert_eq!(eval(&tokens), Err(error));
+    }
+}